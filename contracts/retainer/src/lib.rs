@@ -1,5 +1,5 @@
 #![no_std]
-use soroban_sdk::{contract, contractimpl, contracttype, Env, token, Address, Vec, String};
+use soroban_sdk::{contract, contractimpl, contracttype, symbol_short, vec, xdr::ToXdr, Env, token, Address, Bytes, BytesN, Vec, String, Symbol, IntoVal, TryFromVal, Val};
 
 #[contracttype]
 pub enum StorageKey {
@@ -15,13 +15,123 @@ pub enum StorageKey {
     Retainees(Address),
     // Retainor -> RetainorInfo
     Retainors(Address), 
+    // Retainor, Retainee -> EngagementStats
+    EngagementStats(Address, Address),
+    // Retainor, Token -> EngagementStats
+    RetainorStats(Address, Address),
+    // Retainee, Token -> EngagementStats
+    RetaineeStats(Address, Address),
+    // Retainor, Retainee, Index -> Amount credited back against that receipt
+    Credited(Address, Address, u32),
+    // Retainor, Retainee, Index -> Why that receipt's bill was denied or countered
+    ReceiptReason(Address, Address, u32),
+    // Retainor, Retainee -> CounterOffer
+    CounterOffer(Address, Address),
+    // Retainor, Retainee -> ApprovalPolicy
+    ApprovalPolicy(Address, Address),
+    // Retainor, Retainee -> Vec<AutoApproval> within the current policy window
+    AutoApprovals(Address, Address),
+    // Retainor, Retainee -> BillingCap
+    BillingCap(Address, Address),
+    // Retainor, Retainee -> PeriodSpend
+    PeriodSpend(Address, Address),
+    // Retainor, Retainee -> Vec<Rate>
+    RateCard(Address, Address),
+    // Retainor, Retainee -> Budget
+    Budget(Address, Address),
+    // Retainor, Retainee -> Expiry timestamp
+    Expiry(Address, Address),
+    // Retainor, Retainee -> Evergreen target balance
+    EvergreenTarget(Address, Address),
+    // Retainor, Retainee -> Token paid by transfer_from instead of escrow
+    AllowanceFunded(Address, Address),
+    // Retainor, Retainee -> Vec<SplitShare>
+    PayoutSplit(Address, Address),
+    // Retainor, Retainee -> Address receiving the retainee's payouts
+    PayoutAddress(Address, Address),
+    // Retainor, Retainee -> PayoutAddressChange
+    PayoutAddressChange(Address, Address),
+    // Retainor, Retainee -> PayoutSplitChange
+    PayoutSplitChange(Address, Address),
+    // Retainor, Retainee -> Withholding
+    Withholding(Address, Address),
+    // Retainor, Retainee, Year -> Amount withheld in that calendar year
+    WithheldInYear(Address, Address, u32),
+    // Retainor, Retainee -> Vec<u32> of years with a WithheldInYear total
+    WithholdingYears(Address, Address),
+    // Retainor, Retainee -> Vec<Milestone>
+    Milestones(Address, Address),
+    // Retainor, Retainee -> Stream
+    Stream(Address, Address),
+    // Retainor, Retainee -> PaymentSchedule
+    PaymentSchedule(Address, Address),
+    // Retainor, Retainee -> KeeperFund
+    KeeperFund(Address, Address),
+    // Retainor, Retainee -> Bond
+    Bond(Address, Address),
+    // Retainor, Pool -> Vec<PoolMember>
+    Pool(Address, Address),
+    // Retainor, Member -> Pool the member bills against
+    PoolMembership(Address, Address),
+    // Retainor, Retainee -> Vec<Contribution>
+    Contributions(Address, Address),
+    // Retainor, Retainee -> RefundRule
+    RefundRule(Address, Address),
+    // Retainor -> ed25519 public key used for signed approvals
+    SigningKey(Address),
+    // Retainor -> Next nonce expected on a signed approval
+    SignerNonce(Address),
 }
 
+// Payout address changes only take effect after this many seconds
+const PAYOUT_ADDRESS_DELAY: u64 = 3 * 24 * 60 * 60;
+
+// Installments processed per call, so a long backlog is worked off over several calls
+// instead of running out of budget on every attempt
+const MAX_INSTALLMENTS_PER_CALL: u32 = 25;
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 #[contracttype]
 pub enum ApprovalStatus {
     Approved,
     Denied,
+    Credited,
+    Countered,
+    Withdrawn,
+    Expired,
+    AutoApproved,
+    Milestone,
+    Streamed,
+    Scheduled,
+    Skipped,
+    Slashed,
+    Assigned,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[contracttype]
+pub enum MilestoneStatus {
+    Open,
+    Delivered,
+    Accepted,
+}
+
+// How refunds are split between the retainor and any sponsors who funded the retainer
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[contracttype]
+pub enum RefundRule {
+    ProRata,
+    SponsorsFirst,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[contracttype]
+pub enum ReasonCode {
+    OutOfScope,
+    Duplicate,
+    InsufficientDetail,
+    RateDispute,
+    Other,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -47,6 +157,211 @@ pub struct Receipt {
     pub notes: String,
     pub date: String,
     pub status: ApprovalStatus,
+    // Amount actually paid out of the retainer, which differs from bill.amount on countered bills
+    pub settled_amount: i128,
+    // Part of the settled amount sent to the withholding destination instead of the retainee
+    pub withheld: i128,
+    // History index of the receipt this one amends, e.g. the bill a credit note refunds
+    pub related_receipt: Option<u32>,
+    // Where the settled amount went, empty when it all went to the retainee's own address
+    pub payouts: Vec<Payout>,
+    // Pool member that billed, set on receipts drawn from a pool
+    pub member: Option<Address>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[contracttype]
+pub struct SplitShare {
+    pub recipient: Address,
+    // Share of each payout in basis points
+    pub bps: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[contracttype]
+pub struct Milestone {
+    pub description: String,
+    pub amount: i128,
+    pub due_date: u64,
+    pub status: MilestoneStatus,
+}
+
+// Pay accruing per second from the retained balance between start and end
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[contracttype]
+pub struct Stream {
+    pub rate_per_second: i128,
+    pub start: u64,
+    pub end: u64,
+    // Time up to which accrued pay has been claimed
+    pub claimed_until: u64,
+}
+
+// Fixed installments paid from the retained balance every interval seconds
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[contracttype]
+pub struct PaymentSchedule {
+    pub amount: i128,
+    pub interval: u64,
+    pub count: u32,
+    pub next_due: u64,
+    pub paid: u32,
+    pub skipped: u32,
+}
+
+// Rewards paid to whoever runs a maintenance call that changes the engagement's state,
+// held apart from the retained balance
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[contracttype]
+pub struct KeeperFund {
+    pub token: Address,
+    pub balance: i128,
+    pub reward: i128,
+}
+
+// Performance bond posted by the retainee, which the arbiter can slash to the retainor
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[contracttype]
+pub struct Bond {
+    pub token: Address,
+    pub required: i128,
+    pub posted: i128,
+    pub arbiter: Address,
+}
+
+// Resolution a retainor signs off-chain so any relayer can submit it. The denial reason is
+// signed alongside it, see approval_message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[contracttype]
+pub struct SignedApproval {
+    pub retainor: Address,
+    pub retainee: Address,
+    pub bill_hash: BytesN<32>,
+    pub status: ApprovalStatus,
+    pub notes: String,
+    pub date: String,
+    pub nonce: u64,
+    pub expiry: u64,
+}
+
+// A funder's share of the money still escrowed for an engagement: deposits less refunds
+// and less the funder's pro-rata part of every payout
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[contracttype]
+pub struct Contribution {
+    pub funder: Address,
+    pub amount: i128,
+}
+
+// Retainee allowed to bill against a pool's shared balance, with an optional sub-limit
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[contracttype]
+pub struct PoolMember {
+    pub member: Address,
+    pub limit: Option<i128>,
+    pub spent: i128,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[contracttype]
+pub struct Withholding {
+    // Share of each payout withheld, in basis points
+    pub rate_bps: u32,
+    pub destination: Address,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[contracttype]
+pub struct PayoutAddressChange {
+    pub address: Address,
+    pub effective_at: u64,
+}
+
+// Pending payout split, where an empty split removes the current one
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[contracttype]
+pub struct PayoutSplitChange {
+    pub split: Vec<SplitShare>,
+    pub effective_at: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[contracttype]
+pub struct Payout {
+    pub recipient: Address,
+    pub amount: i128,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[contracttype]
+pub struct CounterOffer {
+    pub amount: i128,
+    pub reason: ReasonCode,
+    pub notes: String,
+    pub date: String,
+}
+
+// Standing approval for small bills, checked by submit_bill
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[contracttype]
+pub struct ApprovalPolicy {
+    pub max_per_bill: i128,
+    pub max_per_period: i128,
+    // Length of the rolling window in seconds
+    pub period: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[contracttype]
+pub struct AutoApproval {
+    pub amount: i128,
+    pub timestamp: u64,
+}
+
+// Maximum paid out of the retainer per fixed period, counted from start
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[contracttype]
+pub struct BillingCap {
+    pub amount: i128,
+    // Length of each period in seconds
+    pub period: u64,
+    pub start: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[contracttype]
+pub struct PeriodSpend {
+    pub period_index: u64,
+    pub spent: i128,
+}
+
+// Hourly rate for a role or task code, applying to bills submitted from effective_from on
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[contracttype]
+pub struct Rate {
+    pub code: Symbol,
+    pub rate: i128,
+    pub effective_from: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[contracttype]
+pub struct TimeEntry {
+    pub code: Symbol,
+    // Time is logged in minutes so partial hours bill exactly; rates are per hour
+    pub minutes: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[contracttype]
+pub struct Budget {
+    pub amount: i128,
+    // Warning levels in basis points of amount, e.g. 7500 for 75%
+    pub thresholds: Vec<u32>,
+    // Payouts past the budget need the retainor's explicit override
+    pub require_override: bool,
+    // Paid out of the retainer since the budget was set
+    pub spent: i128,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -63,18 +378,71 @@ pub struct RetainorInfo {
     pub retainees: Vec<Address>,
 }
 
+// Number of denied or countered bills per ReasonCode
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[contracttype]
+pub struct ReasonCounts {
+    pub out_of_scope: u32,
+    pub duplicate: u32,
+    pub insufficient_detail: u32,
+    pub rate_dispute: u32,
+    pub other: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[contracttype]
+pub struct EngagementStats {
+    pub bills_submitted: u32,
+    pub bills_approved: u32,
+    pub bills_denied: u32,
+    pub bills_countered: u32,
+    pub bills_withdrawn: u32,
+    pub amount_paid: i128,
+    pub amount_deposited: i128,
+    pub amount_withdrawn: i128,
+    pub amount_credited: i128,
+    pub reasons: ReasonCounts,
+}
+
 fn check_positive_amount(amount: i128) {
     if amount <= 0 {
         panic!("Negative amount");
     }
 }
 
+// Calendar year (UTC) of a unix timestamp, see http://howardhinnant.github.io/date_algorithms.html
+fn year_of(timestamp: u64) -> u32 {
+    let days = (timestamp / 86_400) as i64;
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let year = yoe + era * 400;
+    // months are counted from March, so January and February belong to the next year
+    if mp >= 10 {
+        (year + 1) as u32
+    } else {
+        year as u32
+    }
+}
+
+fn count_reason(counts: &mut ReasonCounts, reason: &ReasonCode) {
+    match reason {
+        ReasonCode::OutOfScope => counts.out_of_scope += 1,
+        ReasonCode::Duplicate => counts.duplicate += 1,
+        ReasonCode::InsufficientDetail => counts.insufficient_detail += 1,
+        ReasonCode::RateDispute => counts.rate_dispute += 1,
+        ReasonCode::Other => counts.other += 1,
+    }
+}
+
 fn get_retainer_balance(env: &Env, retainor: &Address, retainee: &Address) -> Option<RetainerBalance> {
     let key = StorageKey::Retainer(retainor.clone(), retainee.clone());
-    let res = env.storage()
+    env.storage()
         .persistent()
-        .get::<_, RetainerBalance>(&key);
-    res
+        .get::<_, RetainerBalance>(&key)
 }
 
 fn get_pending_payment(env: &Env, retainor: &Address, retainee: &Address) -> Option<Bill> {
@@ -108,199 +476,2675 @@ fn get_history_index(env: &Env, retainor: &Address, retainee: &Address) -> u32 {
         .unwrap_or(0u32)
 }
 
-fn set_retainer_balance(env: &Env, retainor: &Address, retainee: &Address, config: RetainerBalance) {
+fn get_counter_offer(env: &Env, retainor: &Address, retainee: &Address) -> Option<CounterOffer> {
     env.storage()
         .persistent()
-        .set::<_, RetainerBalance>(&StorageKey::Retainer(retainor.clone(), retainee.clone()), &config);
+        .get::<_, CounterOffer>(&StorageKey::CounterOffer(retainor.clone(), retainee.clone()))
 }
 
-fn set_pending_payment(env: &Env, retainor: &Address, retainee: &Address, bill: Bill) {
+fn get_approval_policy(env: &Env, retainor: &Address, retainee: &Address) -> Option<ApprovalPolicy> {
     env.storage()
         .persistent()
-        .set::<_, Bill>(&StorageKey::PendingPayment(retainor.clone(), retainee.clone()), &bill);
+        .get::<_, ApprovalPolicy>(&StorageKey::ApprovalPolicy(retainor.clone(), retainee.clone()))
 }
 
-fn set_receipt(env: &Env, retainor: &Address, retainee: &Address, index: u32, receipt: Receipt) {
+// Auto approvals still inside the rolling window of the given policy
+fn get_auto_approvals(env: &Env, retainor: &Address, retainee: &Address, policy: &ApprovalPolicy) -> Vec<AutoApproval> {
+    let approvals = env.storage()
+        .persistent()
+        .get::<_, Vec<AutoApproval>>(&StorageKey::AutoApprovals(retainor.clone(), retainee.clone()))
+        .unwrap_or(Vec::new(env));
+    let window_start = env.ledger().timestamp().saturating_sub(policy.period);
+    let mut recent = Vec::new(env);
+    for approval in approvals.iter() {
+        if approval.timestamp > window_start {
+            recent.push_back(approval);
+        }
+    }
+    recent
+}
+
+fn get_billing_cap(env: &Env, retainor: &Address, retainee: &Address) -> Option<BillingCap> {
     env.storage()
         .persistent()
-        .set::<_, Receipt>(&StorageKey::History(retainor.clone(), retainee.clone(), index), &receipt);
+        .get::<_, BillingCap>(&StorageKey::BillingCap(retainor.clone(), retainee.clone()))
 }
 
-fn set_retainee_info(env: &Env, retainee: &Address, info: RetaineeInfo) {
+// Amount paid out so far in the cap's current period
+fn get_period_spend(env: &Env, retainor: &Address, retainee: &Address, cap: &BillingCap) -> PeriodSpend {
+    let period_index = env.ledger().timestamp().saturating_sub(cap.start) / cap.period;
+    match env.storage()
+        .persistent()
+        .get::<_, PeriodSpend>(&StorageKey::PeriodSpend(retainor.clone(), retainee.clone())) {
+        Some(spend) if spend.period_index == period_index => spend,
+        _ => PeriodSpend {
+            period_index,
+            spent: 0,
+        },
+    }
+}
+
+// Remaining amount that may be paid out this period, if a cap is configured
+fn get_cap_headroom(env: &Env, retainor: &Address, retainee: &Address) -> Option<i128> {
+    let cap = get_billing_cap(env, retainor, retainee)?;
+    let spend = get_period_spend(env, retainor, retainee, &cap);
+    Some(cap.amount.checked_sub(spend.spent).unwrap().max(0))
+}
+
+fn get_rate_card(env: &Env, retainor: &Address, retainee: &Address) -> Vec<Rate> {
     env.storage()
         .persistent()
-        .set::<_, RetaineeInfo>(&StorageKey::Retainees(retainee.clone()), &info);
+        .get::<_, Vec<Rate>>(&StorageKey::RateCard(retainor.clone(), retainee.clone()))
+        .unwrap_or(Vec::new(env))
 }
 
-fn set_retainor_info(env: &Env, retainor: &Address, info: RetainorInfo) {
+// Latest rate for the code that is already in effect at the current ledger time
+fn get_current_rate(env: &Env, retainor: &Address, retainee: &Address, code: &Symbol) -> Option<i128> {
+    let now = env.ledger().timestamp();
+    let mut current: Option<Rate> = None;
+    for rate in get_rate_card(env, retainor, retainee).iter() {
+        if rate.code != *code || rate.effective_from > now {
+            continue;
+        }
+        match &current {
+            Some(c) if c.effective_from >= rate.effective_from => {}
+            _ => current = Some(rate),
+        }
+    }
+    current.map(|rate| rate.rate)
+}
+
+fn compute_time_bill(env: &Env, retainor: &Address, retainee: &Address, entries: &Vec<TimeEntry>) -> i128 {
+    // sum in rate-minutes and round down once, so splitting time across entries costs nothing
+    let mut rate_minutes: i128 = 0;
+    for entry in entries.iter() {
+        let rate = match get_current_rate(env, retainor, retainee, &entry.code) {
+            Some(rate) => rate,
+            None => panic!("No rate for code"),
+        };
+        let cost = rate.checked_mul(entry.minutes as i128).unwrap();
+        rate_minutes = rate_minutes.checked_add(cost).unwrap();
+    }
+    rate_minutes / 60
+}
+
+fn get_budget(env: &Env, retainor: &Address, retainee: &Address) -> Option<Budget> {
     env.storage()
         .persistent()
-        .set::<_, RetainorInfo>(&StorageKey::Retainors(retainor.clone()), &info);
+        .get::<_, Budget>(&StorageKey::Budget(retainor.clone(), retainee.clone()))
 }
 
-fn set_history_index(env: &Env, retainor: &Address, retainee: &Address, index: u32) {
+// The engagements whose budgets a bill counts against: its own and, for a pool member, the pool's
+fn budget_holders(env: &Env, retainor: &Address, retainee: &Address) -> Vec<Address> {
+    let mut holders = vec![env, retainee.clone()];
+    if let Some(pool) = get_member_pool(env, retainor, retainee) {
+        holders.push_back(pool);
+    }
+    holders
+}
+
+// Whether paying the amount would go past a budget that requires an override
+fn exceeds_budget(env: &Env, retainor: &Address, retainee: &Address, amount: i128) -> bool {
+    budget_holders(env, retainor, retainee).iter().any(|holder| match get_budget(env, retainor, &holder) {
+        Some(budget) => budget.require_override && budget.spent.checked_add(amount).unwrap() > budget.amount,
+        None => false,
+    })
+}
+
+fn get_expiry(env: &Env, retainor: &Address, retainee: &Address) -> Option<u64> {
     env.storage()
         .persistent()
-        .set::<_, u32>(&StorageKey::HistoryIndex(retainor.clone(), retainee.clone()), &index);
+        .get::<_, u64>(&StorageKey::Expiry(retainor.clone(), retainee.clone()))
 }
 
-fn clear_pending_payment(env: &Env, retainor: &Address, retainee: &Address) {
+fn is_expired(env: &Env, retainor: &Address, retainee: &Address) -> bool {
+    match get_expiry(env, retainor, retainee) {
+        Some(expires_at) => env.ledger().timestamp() >= expires_at,
+        None => false,
+    }
+}
+
+fn get_evergreen_target(env: &Env, retainor: &Address, retainee: &Address) -> Option<i128> {
     env.storage()
         .persistent()
-        .remove(&StorageKey::PendingPayment(retainor.clone(), retainee.clone()));
+        .get::<_, i128>(&StorageKey::EvergreenTarget(retainor.clone(), retainee.clone()))
 }
 
-#[contract]
-pub struct Contract;
+fn get_allowance_token(env: &Env, retainor: &Address, retainee: &Address) -> Option<Address> {
+    env.storage()
+        .persistent()
+        .get::<_, Address>(&StorageKey::AllowanceFunded(retainor.clone(), retainee.clone()))
+}
 
-#[contractimpl]
-impl Contract {
+// Amount the contract may currently pull from the retainor of an allowance funded engagement
+fn get_available_allowance(env: &Env, retainor: &Address, token: &Address) -> i128 {
+    let client = token::Client::new(env, token);
+    let allowance = client.allowance(retainor, &env.current_contract_address());
+    allowance.min(client.balance(retainor))
+}
 
-    pub fn submit_bill(env: Env, retainor: Address, retainee: Address, amount: i128, notes: String, date: String) {
-        retainee.require_auth();
-        check_positive_amount(amount);
-        match get_pending_payment(&env, &retainor, &retainee) {
-            Some(_) => panic!("Pending payment already exists"),
-            None => {}
-        };
-        let retained_balance = match get_retainer_balance(&env, &retainor, &retainee) {
-            Some(balance) => balance,
-            None => panic!("No retained balance"),
-        };
-        if retained_balance.amount < amount {
-            panic!("Insufficient retained balance");
+fn get_payout_split_change(env: &Env, retainor: &Address, retainee: &Address) -> Option<PayoutSplitChange> {
+    env.storage()
+        .persistent()
+        .get::<_, PayoutSplitChange>(&StorageKey::PayoutSplitChange(retainor.clone(), retainee.clone()))
+}
+
+// Split currently applied to the retainee's payouts, including a change whose delay has passed
+fn get_payout_split(env: &Env, retainor: &Address, retainee: &Address) -> Option<Vec<SplitShare>> {
+    if let Some(change) = get_payout_split_change(env, retainor, retainee) {
+        if env.ledger().timestamp() >= change.effective_at {
+            return if change.split.is_empty() { None } else { Some(change.split) };
         }
-        let bill = Bill {
-            amount,
-            notes,
-            date,
-            token: retained_balance.token.clone(),
-        };
-        set_pending_payment(&env, &retainor, &retainee, bill);
     }
+    env.storage()
+        .persistent()
+        .get::<_, Vec<SplitShare>>(&StorageKey::PayoutSplit(retainor.clone(), retainee.clone()))
+}
 
-    pub fn unsubmit_bill(env: Env, retainor: Address, retainee: Address) {
-        retainee.require_auth();
-        clear_pending_payment(&env, &retainor, &retainee);
+// Divides an amount across the split, leaving any rounding remainder with the first recipient
+fn split_amount(env: &Env, split: &Vec<SplitShare>, amount: i128) -> Vec<Payout> {
+    let mut payouts = Vec::new(env);
+    let mut remainder = amount;
+    for share in split.iter() {
+        let share_amount = amount.checked_mul(share.bps as i128).unwrap() / 10_000;
+        remainder = remainder.checked_sub(share_amount).unwrap();
+        payouts.push_back(Payout {
+            recipient: share.recipient,
+            amount: share_amount,
+        });
     }
+    let mut first = payouts.get(0).unwrap();
+    first.amount = first.amount.checked_add(remainder).unwrap();
+    payouts.set(0, first);
+    payouts
+}
 
-    pub fn resolve_bill(env: Env, retainor: Address, retainee: Address, status: ApprovalStatus, notes: String, date: String) {
-        retainor.require_auth();
-        let bill = match get_pending_payment(&env, &retainor, &retainee) {
-            Some(bill) => bill,
-            None => panic!("No pending payment"),
-        };
-        let receipt = Receipt {
-            bill: bill.clone(),
-            notes,
-            date,
-            status: status.clone(),
-        };
-        if status == ApprovalStatus::Approved {
-            // send payment
-            token::Client::new(&env, &bill.token).transfer(&env.current_contract_address(), &retainee, &bill.amount);
-            // update retained balance
-            let mut retainer_balance = get_retainer_balance(&env, &retainor, &retainee).unwrap();
-            retainer_balance.amount = retainer_balance.amount.checked_sub(bill.amount).unwrap();
-            set_retainer_balance(&env, &retainor, &retainee, retainer_balance);
-        }
-        let index = get_history_index(&env, &retainor, &retainee) + 1;
-        set_receipt(&env, &retainor, &retainee, index, receipt);
-        set_history_index(&env, &retainor, &retainee, index);
-        clear_pending_payment(&env, &retainor, &retainee);
-    }
+fn get_payout_address_change(env: &Env, retainor: &Address, retainee: &Address) -> Option<PayoutAddressChange> {
+    env.storage()
+        .persistent()
+        .get::<_, PayoutAddressChange>(&StorageKey::PayoutAddressChange(retainor.clone(), retainee.clone()))
+}
 
-    pub fn view_bill(env: Env, retainor: Address, retainee: Address) -> Option<Bill> {
-        get_pending_payment(&env, &retainor, &retainee)
+// Address currently receiving the retainee's payouts, including a change whose delay has passed
+fn get_payout_address(env: &Env, retainor: &Address, retainee: &Address) -> Address {
+    if let Some(change) = get_payout_address_change(env, retainor, retainee) {
+        if env.ledger().timestamp() >= change.effective_at {
+            return change.address;
+        }
     }
+    env.storage()
+        .persistent()
+        .get::<_, Address>(&StorageKey::PayoutAddress(retainor.clone(), retainee.clone()))
+        .unwrap_or(retainee.clone())
+}
 
-    pub fn view_bill_unwrap(env: Env, retainor: Address, retainee: Address) -> Bill {
-        get_pending_payment(&env, &retainor, &retainee).unwrap()
-    }
+fn get_withholding(env: &Env, retainor: &Address, retainee: &Address) -> Option<Withholding> {
+    env.storage()
+        .persistent()
+        .get::<_, Withholding>(&StorageKey::Withholding(retainor.clone(), retainee.clone()))
+}
 
-    pub fn view_receipt(env: Env, retainor: Address, retainee: Address, index: u32) -> Option<Receipt> {
-        get_receipt(&env, &retainor, &retainee, index)
+fn get_withheld_in_year(env: &Env, retainor: &Address, retainee: &Address, year: u32) -> i128 {
+    env.storage()
+        .persistent()
+        .get::<_, i128>(&StorageKey::WithheldInYear(retainor.clone(), retainee.clone(), year))
+        .unwrap_or(0)
+}
+
+fn get_milestones(env: &Env, retainor: &Address, retainee: &Address) -> Vec<Milestone> {
+    env.storage()
+        .persistent()
+        .get::<_, Vec<Milestone>>(&StorageKey::Milestones(retainor.clone(), retainee.clone()))
+        .unwrap_or(Vec::new(env))
+}
+
+fn get_milestone(env: &Env, retainor: &Address, retainee: &Address, index: u32) -> Milestone {
+    match get_milestones(env, retainor, retainee).get(index) {
+        Some(milestone) => milestone,
+        None => panic!("No such milestone"),
     }
+}
 
-    pub fn history_index(env: Env, retainor: Address, retainee: Address) -> u32 {
-        get_history_index(&env, &retainor, &retainee)
+fn get_stream(env: &Env, retainor: &Address, retainee: &Address) -> Option<Stream> {
+    env.storage()
+        .persistent()
+        .get::<_, Stream>(&StorageKey::Stream(retainor.clone(), retainee.clone()))
+}
+
+// Pay accrued between the last claim and the given time
+fn stream_accrued(stream: &Stream, until: u64) -> i128 {
+    let until = until.min(stream.end);
+    if until <= stream.claimed_until {
+        return 0;
     }
+    stream.rate_per_second.checked_mul((until - stream.claimed_until) as i128).unwrap()
+}
 
-    pub fn view_receipt_history_range(env: Env, retainor: Address, retainee: Address, start: u32, end: u32) -> Vec<Receipt> {
-        let mut history = Vec::new(&env);
-        for i in start..=end {
-            match get_receipt(&env, &retainor, &retainee, i) {
-                Some(receipt) => {
-                    history.push_back(receipt);
-                }
-                None => {}
-            }
+// Retained balance reserved for milestones that have not been accepted yet and unclaimed stream pay
+fn get_committed(env: &Env, retainor: &Address, retainee: &Address) -> i128 {
+    let mut committed: i128 = 0;
+    for milestone in get_milestones(env, retainor, retainee).iter() {
+        if milestone.status != MilestoneStatus::Accepted {
+            committed = committed.checked_add(milestone.amount).unwrap();
         }
-        history
     }
-
-    pub fn view_receipt_history(env: Env, retainor: Address, retainee: Address, limit: u32) -> Vec<Receipt> {
-        let index = get_history_index(&env, &retainor, &retainee);
-        if index < 1 {
-            return Vec::new(&env);
+    if let Some(stream) = get_stream(env, retainor, retainee) {
+        committed = committed.checked_add(stream_accrued(&stream, stream.end)).unwrap();
+    }
+    // funds set aside for the bill awaiting resolution
+    if let Some(bill) = get_pending_payment(env, retainor, retainee) {
+        committed = committed.checked_add(bill.amount).unwrap();
+    }
+    // and for bills pool members have submitted against this pool
+    for member in get_pool_members(env, retainor, retainee).iter() {
+        if let Some(bill) = get_pending_payment(env, retainor, &member.member) {
+            committed = committed.checked_add(bill.amount).unwrap();
+        }
+    }
+    committed
+}
+
+// True if a bill awaits resolution on the engagement or, for a pool, on any of its members
+fn has_pending_payment(env: &Env, retainor: &Address, retainee: &Address) -> bool {
+    get_pending_payment(env, retainor, retainee).is_some()
+        || get_pool_members(env, retainor, retainee)
+            .iter()
+            .any(|member| get_pending_payment(env, retainor, &member.member).is_some())
+}
+
+fn get_credited(env: &Env, retainor: &Address, retainee: &Address, index: u32) -> i128 {
+    env.storage()
+        .persistent()
+        .get::<_, i128>(&StorageKey::Credited(retainor.clone(), retainee.clone(), index))
+        .unwrap_or(0)
+}
+
+fn get_stats(env: &Env, key: &StorageKey) -> EngagementStats {
+    env.storage()
+        .persistent()
+        .get::<_, EngagementStats>(key)
+        .unwrap_or_default()
+}
+
+fn set_retainer_balance(env: &Env, retainor: &Address, retainee: &Address, config: RetainerBalance) {
+    env.storage()
+        .persistent()
+        .set::<_, RetainerBalance>(&StorageKey::Retainer(retainor.clone(), retainee.clone()), &config);
+}
+
+fn set_pending_payment(env: &Env, retainor: &Address, retainee: &Address, bill: Bill) {
+    env.storage()
+        .persistent()
+        .set::<_, Bill>(&StorageKey::PendingPayment(retainor.clone(), retainee.clone()), &bill);
+}
+
+fn set_receipt(env: &Env, retainor: &Address, retainee: &Address, index: u32, receipt: Receipt) {
+    env.storage()
+        .persistent()
+        .set::<_, Receipt>(&StorageKey::History(retainor.clone(), retainee.clone(), index), &receipt);
+}
+
+fn set_retainee_info(env: &Env, retainee: &Address, info: RetaineeInfo) {
+    env.storage()
+        .persistent()
+        .set::<_, RetaineeInfo>(&StorageKey::Retainees(retainee.clone()), &info);
+}
+
+fn set_retainor_info(env: &Env, retainor: &Address, info: RetainorInfo) {
+    env.storage()
+        .persistent()
+        .set::<_, RetainorInfo>(&StorageKey::Retainors(retainor.clone()), &info);
+}
+
+fn set_history_index(env: &Env, retainor: &Address, retainee: &Address, index: u32) {
+    env.storage()
+        .persistent()
+        .set::<_, u32>(&StorageKey::HistoryIndex(retainor.clone(), retainee.clone()), &index);
+}
+
+fn set_counter_offer(env: &Env, retainor: &Address, retainee: &Address, offer: CounterOffer) {
+    env.storage()
+        .persistent()
+        .set::<_, CounterOffer>(&StorageKey::CounterOffer(retainor.clone(), retainee.clone()), &offer);
+}
+
+fn set_approval_policy(env: &Env, retainor: &Address, retainee: &Address, policy: ApprovalPolicy) {
+    env.storage()
+        .persistent()
+        .set::<_, ApprovalPolicy>(&StorageKey::ApprovalPolicy(retainor.clone(), retainee.clone()), &policy);
+}
+
+fn set_auto_approvals(env: &Env, retainor: &Address, retainee: &Address, approvals: Vec<AutoApproval>) {
+    env.storage()
+        .persistent()
+        .set::<_, Vec<AutoApproval>>(&StorageKey::AutoApprovals(retainor.clone(), retainee.clone()), &approvals);
+}
+
+fn clear_approval_policy(env: &Env, retainor: &Address, retainee: &Address) {
+    env.storage()
+        .persistent()
+        .remove(&StorageKey::ApprovalPolicy(retainor.clone(), retainee.clone()));
+    env.storage()
+        .persistent()
+        .remove(&StorageKey::AutoApprovals(retainor.clone(), retainee.clone()));
+}
+
+// Records the bill against the standing approval window if it fits the policy
+fn try_auto_approve(env: &Env, retainor: &Address, retainee: &Address, amount: i128) -> bool {
+    let policy = match get_approval_policy(env, retainor, retainee) {
+        Some(policy) => policy,
+        None => return false,
+    };
+    if amount > policy.max_per_bill {
+        return false;
+    }
+    let mut approvals = get_auto_approvals(env, retainor, retainee, &policy);
+    let mut total = amount;
+    for approval in approvals.iter() {
+        total = total.checked_add(approval.amount).unwrap();
+    }
+    if total > policy.max_per_period {
+        return false;
+    }
+    approvals.push_back(AutoApproval {
+        amount,
+        timestamp: env.ledger().timestamp(),
+    });
+    set_auto_approvals(env, retainor, retainee, approvals);
+    true
+}
+
+fn set_billing_cap(env: &Env, retainor: &Address, retainee: &Address, cap: BillingCap) {
+    env.storage()
+        .persistent()
+        .set::<_, BillingCap>(&StorageKey::BillingCap(retainor.clone(), retainee.clone()), &cap);
+}
+
+fn set_period_spend(env: &Env, retainor: &Address, retainee: &Address, spend: PeriodSpend) {
+    env.storage()
+        .persistent()
+        .set::<_, PeriodSpend>(&StorageKey::PeriodSpend(retainor.clone(), retainee.clone()), &spend);
+}
+
+fn clear_billing_cap(env: &Env, retainor: &Address, retainee: &Address) {
+    env.storage()
+        .persistent()
+        .remove(&StorageKey::BillingCap(retainor.clone(), retainee.clone()));
+    env.storage()
+        .persistent()
+        .remove(&StorageKey::PeriodSpend(retainor.clone(), retainee.clone()));
+}
+
+fn check_cap_headroom(env: &Env, retainor: &Address, retainee: &Address, amount: i128) {
+    if let Some(headroom) = get_cap_headroom(env, retainor, retainee) {
+        if amount > headroom {
+            panic!("Billing cap exceeded");
+        }
+    }
+}
+
+// Counts a payout against the current period of the billing cap, if any
+fn record_period_spend(env: &Env, retainor: &Address, retainee: &Address, amount: i128) {
+    let cap = match get_billing_cap(env, retainor, retainee) {
+        Some(cap) => cap,
+        None => return,
+    };
+    let mut spend = get_period_spend(env, retainor, retainee, &cap);
+    spend.spent = spend.spent.checked_add(amount).unwrap();
+    if spend.spent > cap.amount {
+        panic!("Billing cap exceeded");
+    }
+    set_period_spend(env, retainor, retainee, spend);
+}
+
+fn set_rate_card(env: &Env, retainor: &Address, retainee: &Address, rate_card: Vec<Rate>) {
+    env.storage()
+        .persistent()
+        .set::<_, Vec<Rate>>(&StorageKey::RateCard(retainor.clone(), retainee.clone()), &rate_card);
+}
+
+fn set_budget(env: &Env, retainor: &Address, retainee: &Address, budget: Budget) {
+    env.storage()
+        .persistent()
+        .set::<_, Budget>(&StorageKey::Budget(retainor.clone(), retainee.clone()), &budget);
+}
+
+fn clear_budget(env: &Env, retainor: &Address, retainee: &Address) {
+    env.storage()
+        .persistent()
+        .remove(&StorageKey::Budget(retainor.clone(), retainee.clone()));
+}
+
+// Emits a warning for every threshold crossed by adding a newly submitted bill to the amount spent
+fn check_budget_thresholds(env: &Env, retainor: &Address, retainee: &Address, amount: i128) {
+    for holder in budget_holders(env, retainor, retainee).iter() {
+        let budget = match get_budget(env, retainor, &holder) {
+            Some(budget) => budget,
+            None => continue,
+        };
+        let before = budget.spent.checked_mul(10_000).unwrap();
+        let after = budget.spent.checked_add(amount).unwrap().checked_mul(10_000).unwrap();
+        for threshold in budget.thresholds.iter() {
+            let level = budget.amount.checked_mul(threshold as i128).unwrap();
+            if before < level && after >= level {
+                env.events().publish(
+                    (symbol_short!("budget"), retainor.clone(), holder.clone()),
+                    (threshold, budget.spent.checked_add(amount).unwrap(), budget.amount),
+                );
+            }
+        }
+    }
+}
+
+fn record_budget_spend(env: &Env, retainor: &Address, retainee: &Address, amount: i128) {
+    for holder in budget_holders(env, retainor, retainee).iter() {
+        if let Some(mut budget) = get_budget(env, retainor, &holder) {
+            budget.spent = budget.spent.checked_add(amount).unwrap();
+            set_budget(env, retainor, &holder, budget);
+        }
+    }
+}
+
+fn set_expiry(env: &Env, retainor: &Address, retainee: &Address, expires_at: u64) {
+    env.storage()
+        .persistent()
+        .set::<_, u64>(&StorageKey::Expiry(retainor.clone(), retainee.clone()), &expires_at);
+}
+
+fn set_evergreen_target(env: &Env, retainor: &Address, retainee: &Address, target: i128) {
+    env.storage()
+        .persistent()
+        .set::<_, i128>(&StorageKey::EvergreenTarget(retainor.clone(), retainee.clone()), &target);
+}
+
+fn clear_evergreen_target(env: &Env, retainor: &Address, retainee: &Address) {
+    env.storage()
+        .persistent()
+        .remove(&StorageKey::EvergreenTarget(retainor.clone(), retainee.clone()));
+}
+
+// Tops an evergreen retainer back up to its target from the retainor's token allowance
+fn replenish(env: &Env, retainor: &Address, retainee: &Address) -> i128 {
+    // an expired retainer takes no more bills, so there is nothing to top up for
+    if is_expired(env, retainor, retainee) {
+        return 0;
+    }
+    let target = match get_evergreen_target(env, retainor, retainee) {
+        Some(target) => target,
+        None => return 0,
+    };
+    let mut retainer_balance = match get_retainer_balance(env, retainor, retainee) {
+        Some(balance) => balance,
+        None => return 0,
+    };
+    let shortfall = target.checked_sub(retainer_balance.amount).unwrap();
+    if shortfall <= 0 {
+        return 0;
+    }
+    let client = token::Client::new(env, &retainer_balance.token);
+    let allowance = client.allowance(retainor, &env.current_contract_address());
+    if allowance < shortfall || client.balance(retainor) < shortfall {
+        env.events().publish(
+            (Symbol::new(env, "replenish_failed"), retainor.clone(), retainee.clone()),
+            (shortfall, allowance),
+        );
+        return 0;
+    }
+    client.transfer_from(&env.current_contract_address(), retainor, &env.current_contract_address(), &shortfall);
+    retainer_balance.amount = target;
+    update_stats(env, retainor, retainee, &retainer_balance.token, |stats| {
+        stats.amount_deposited = stats.amount_deposited.checked_add(shortfall).unwrap();
+    });
+    record_contribution(env, retainor, retainee, retainor, shortfall);
+    set_retainer_balance(env, retainor, retainee, retainer_balance);
+    shortfall
+}
+
+fn set_allowance_token(env: &Env, retainor: &Address, retainee: &Address, token: Address) {
+    env.storage()
+        .persistent()
+        .set::<_, Address>(&StorageKey::AllowanceFunded(retainor.clone(), retainee.clone()), &token);
+}
+
+fn clear_allowance_token(env: &Env, retainor: &Address, retainee: &Address) {
+    env.storage()
+        .persistent()
+        .remove(&StorageKey::AllowanceFunded(retainor.clone(), retainee.clone()));
+}
+
+fn set_payout_split(env: &Env, retainor: &Address, retainee: &Address, split: Vec<SplitShare>) {
+    env.storage()
+        .persistent()
+        .set::<_, Vec<SplitShare>>(&StorageKey::PayoutSplit(retainor.clone(), retainee.clone()), &split);
+}
+
+fn clear_payout_split(env: &Env, retainor: &Address, retainee: &Address) {
+    env.storage()
+        .persistent()
+        .remove(&StorageKey::PayoutSplit(retainor.clone(), retainee.clone()));
+}
+
+fn set_payout_split_change(env: &Env, retainor: &Address, retainee: &Address, change: PayoutSplitChange) {
+    env.storage()
+        .persistent()
+        .set::<_, PayoutSplitChange>(&StorageKey::PayoutSplitChange(retainor.clone(), retainee.clone()), &change);
+}
+
+fn clear_payout_split_change(env: &Env, retainor: &Address, retainee: &Address) {
+    env.storage()
+        .persistent()
+        .remove(&StorageKey::PayoutSplitChange(retainor.clone(), retainee.clone()));
+}
+
+// Folds a split change that has already taken effect into the current split
+fn settle_payout_split(env: &Env, retainor: &Address, retainee: &Address) {
+    match get_payout_split(env, retainor, retainee) {
+        Some(split) => set_payout_split(env, retainor, retainee, split),
+        None => clear_payout_split(env, retainor, retainee),
+    }
+}
+
+fn set_payout_address(env: &Env, retainor: &Address, retainee: &Address, address: Address) {
+    env.storage()
+        .persistent()
+        .set::<_, Address>(&StorageKey::PayoutAddress(retainor.clone(), retainee.clone()), &address);
+}
+
+fn set_payout_address_change(env: &Env, retainor: &Address, retainee: &Address, change: PayoutAddressChange) {
+    env.storage()
+        .persistent()
+        .set::<_, PayoutAddressChange>(&StorageKey::PayoutAddressChange(retainor.clone(), retainee.clone()), &change);
+}
+
+fn clear_payout_address_change(env: &Env, retainor: &Address, retainee: &Address) {
+    env.storage()
+        .persistent()
+        .remove(&StorageKey::PayoutAddressChange(retainor.clone(), retainee.clone()));
+}
+
+fn set_withholding(env: &Env, retainor: &Address, retainee: &Address, withholding: Withholding) {
+    env.storage()
+        .persistent()
+        .set::<_, Withholding>(&StorageKey::Withholding(retainor.clone(), retainee.clone()), &withholding);
+}
+
+fn clear_withholding(env: &Env, retainor: &Address, retainee: &Address) {
+    env.storage()
+        .persistent()
+        .remove(&StorageKey::Withholding(retainor.clone(), retainee.clone()));
+}
+
+fn record_withheld(env: &Env, retainor: &Address, retainee: &Address, amount: i128) {
+    let year = year_of(env.ledger().timestamp());
+    let total = get_withheld_in_year(env, retainor, retainee, year).checked_add(amount).unwrap();
+    env.storage()
+        .persistent()
+        .set::<_, i128>(&StorageKey::WithheldInYear(retainor.clone(), retainee.clone(), year), &total);
+    let mut years = get_withholding_years(env, retainor, retainee);
+    if !years.contains(year) {
+        years.push_back(year);
+        env.storage()
+            .persistent()
+            .set::<_, Vec<u32>>(&StorageKey::WithholdingYears(retainor.clone(), retainee.clone()), &years);
+    }
+}
+
+fn get_withholding_years(env: &Env, retainor: &Address, retainee: &Address) -> Vec<u32> {
+    env.storage()
+        .persistent()
+        .get::<_, Vec<u32>>(&StorageKey::WithholdingYears(retainor.clone(), retainee.clone()))
+        .unwrap_or(Vec::new(env))
+}
+
+fn set_milestone(env: &Env, retainor: &Address, retainee: &Address, index: u32, milestone: Milestone) {
+    let mut milestones = get_milestones(env, retainor, retainee);
+    milestones.set(index, milestone);
+    env.storage()
+        .persistent()
+        .set::<_, Vec<Milestone>>(&StorageKey::Milestones(retainor.clone(), retainee.clone()), &milestones);
+}
+
+fn push_milestone(env: &Env, retainor: &Address, retainee: &Address, milestone: Milestone) -> u32 {
+    let mut milestones = get_milestones(env, retainor, retainee);
+    milestones.push_back(milestone);
+    env.storage()
+        .persistent()
+        .set::<_, Vec<Milestone>>(&StorageKey::Milestones(retainor.clone(), retainee.clone()), &milestones);
+    milestones.len() - 1
+}
+
+fn set_stream(env: &Env, retainor: &Address, retainee: &Address, stream: Stream) {
+    env.storage()
+        .persistent()
+        .set::<_, Stream>(&StorageKey::Stream(retainor.clone(), retainee.clone()), &stream);
+}
+
+fn clear_stream(env: &Env, retainor: &Address, retainee: &Address) {
+    env.storage()
+        .persistent()
+        .remove(&StorageKey::Stream(retainor.clone(), retainee.clone()));
+}
+
+// Pays out stream pay accrued up to now and records it in the history
+fn claim_stream(env: &Env, retainor: &Address, retainee: &Address, mut stream: Stream, date: String) -> i128 {
+    let now = env.ledger().timestamp();
+    let amount = stream_accrued(&stream, now);
+    if amount == 0 {
+        return 0;
+    }
+    stream.claimed_until = now.min(stream.end);
+    set_stream(env, retainor, retainee, stream);
+    let token = get_retainer_balance(env, retainor, retainee).unwrap().token;
+    // the stream was escrowed in full when it started, so the billing cap can't hold it back
+    let (payouts, withheld) = pay_uncapped(env, retainor, retainee, &token, amount);
+    update_stats(env, retainor, retainee, &token, |stats| {
+        stats.amount_paid = stats.amount_paid.checked_add(amount).unwrap();
+    });
+    let notes = String::from_str(env, "");
+    let receipt = Receipt {
+        bill: Bill {
+            amount,
+            token,
+            notes: notes.clone(),
+            date: date.clone(),
+        },
+        notes,
+        date,
+        status: ApprovalStatus::Streamed,
+        settled_amount: amount,
+        withheld,
+        related_receipt: None,
+        payouts,
+        member: None,
+    };
+    push_receipt(env, retainor, retainee, receipt);
+    amount
+}
+
+fn get_payment_schedule(env: &Env, retainor: &Address, retainee: &Address) -> Option<PaymentSchedule> {
+    env.storage()
+        .persistent()
+        .get::<_, PaymentSchedule>(&StorageKey::PaymentSchedule(retainor.clone(), retainee.clone()))
+}
+
+fn set_payment_schedule(env: &Env, retainor: &Address, retainee: &Address, schedule: PaymentSchedule) {
+    env.storage()
+        .persistent()
+        .set::<_, PaymentSchedule>(&StorageKey::PaymentSchedule(retainor.clone(), retainee.clone()), &schedule);
+}
+
+fn clear_payment_schedule(env: &Env, retainor: &Address, retainee: &Address) {
+    env.storage()
+        .persistent()
+        .remove(&StorageKey::PaymentSchedule(retainor.clone(), retainee.clone()));
+}
+
+// Whether an installment can be paid without touching committed funds or breaking a cap or budget
+fn can_pay_installment(env: &Env, retainor: &Address, retainee: &Address, amount: i128) -> bool {
+    let retained = match get_retainer_balance(env, retainor, retainee) {
+        Some(balance) => balance.amount,
+        None => return false,
+    };
+    // the pending bill, milestones and streams are paid before any installment
+    if retained.checked_sub(get_committed(env, retainor, retainee)).unwrap() < amount {
+        return false;
+    }
+    if let Some(headroom) = get_cap_headroom(env, retainor, retainee) {
+        if amount > headroom {
+            return false;
+        }
+    }
+    !exceeds_budget(env, retainor, retainee, amount)
+}
+
+fn get_keeper_fund(env: &Env, retainor: &Address, retainee: &Address) -> Option<KeeperFund> {
+    env.storage()
+        .persistent()
+        .get::<_, KeeperFund>(&StorageKey::KeeperFund(retainor.clone(), retainee.clone()))
+}
+
+fn set_keeper_fund(env: &Env, retainor: &Address, retainee: &Address, fund: KeeperFund) {
+    env.storage()
+        .persistent()
+        .set::<_, KeeperFund>(&StorageKey::KeeperFund(retainor.clone(), retainee.clone()), &fund);
+}
+
+// Pays the keeper reward if the fund still covers it, only called once an action has changed state
+fn pay_keeper_reward(env: &Env, retainor: &Address, retainee: &Address, keeper: &Address) -> i128 {
+    let mut fund = match get_keeper_fund(env, retainor, retainee) {
+        Some(fund) => fund,
+        None => return 0,
+    };
+    if fund.balance < fund.reward {
+        return 0;
+    }
+    fund.balance = fund.balance.checked_sub(fund.reward).unwrap();
+    token::Client::new(env, &fund.token).transfer(&env.current_contract_address(), keeper, &fund.reward);
+    let reward = fund.reward;
+    set_keeper_fund(env, retainor, retainee, fund);
+    reward
+}
+
+fn get_bond(env: &Env, retainor: &Address, retainee: &Address) -> Option<Bond> {
+    env.storage()
+        .persistent()
+        .get::<_, Bond>(&StorageKey::Bond(retainor.clone(), retainee.clone()))
+}
+
+fn set_bond(env: &Env, retainor: &Address, retainee: &Address, bond: Bond) {
+    env.storage()
+        .persistent()
+        .set::<_, Bond>(&StorageKey::Bond(retainor.clone(), retainee.clone()), &bond);
+}
+
+// Hands whatever is left of the bond back to the retainee
+fn return_bond(env: &Env, retainor: &Address, retainee: &Address) {
+    if let Some(bond) = get_bond(env, retainor, retainee) {
+        if bond.posted > 0 {
+            token::Client::new(env, &bond.token).transfer(&env.current_contract_address(), retainee, &bond.posted);
+        }
+        clear_bond(env, retainor, retainee);
+    }
+}
+
+fn clear_bond(env: &Env, retainor: &Address, retainee: &Address) {
+    env.storage()
+        .persistent()
+        .remove(&StorageKey::Bond(retainor.clone(), retainee.clone()));
+}
+
+// Moves a single persistent entry from one storage key to another, if present
+fn move_entry<V: IntoVal<Env, Val> + TryFromVal<Env, Val>>(env: &Env, from: StorageKey, to: StorageKey) {
+    if let Some(value) = env.storage().persistent().get::<_, V>(&from) {
+        env.storage().persistent().set::<_, V>(&to, &value);
+        env.storage().persistent().remove(&from);
+    }
+}
+
+// True if anything at all is stored for the (retainor, retainee) pair. Indexed entries
+// such as receipts and yearly totals imply HistoryIndex or WithholdingYears.
+fn engagement_exists(env: &Env, retainor: &Address, retainee: &Address) -> bool {
+    let (r, e) = (retainor.clone(), retainee.clone());
+    let keys = [
+        StorageKey::Retainer(r.clone(), e.clone()),
+        StorageKey::PendingPayment(r.clone(), e.clone()),
+        StorageKey::HistoryIndex(r.clone(), e.clone()),
+        StorageKey::EngagementStats(r.clone(), e.clone()),
+        StorageKey::CounterOffer(r.clone(), e.clone()),
+        StorageKey::ApprovalPolicy(r.clone(), e.clone()),
+        StorageKey::AutoApprovals(r.clone(), e.clone()),
+        StorageKey::BillingCap(r.clone(), e.clone()),
+        StorageKey::PeriodSpend(r.clone(), e.clone()),
+        StorageKey::RateCard(r.clone(), e.clone()),
+        StorageKey::Budget(r.clone(), e.clone()),
+        StorageKey::Expiry(r.clone(), e.clone()),
+        StorageKey::EvergreenTarget(r.clone(), e.clone()),
+        StorageKey::AllowanceFunded(r.clone(), e.clone()),
+        StorageKey::PayoutSplit(r.clone(), e.clone()),
+        StorageKey::PayoutSplitChange(r.clone(), e.clone()),
+        StorageKey::PayoutAddress(r.clone(), e.clone()),
+        StorageKey::PayoutAddressChange(r.clone(), e.clone()),
+        StorageKey::Withholding(r.clone(), e.clone()),
+        StorageKey::WithholdingYears(r.clone(), e.clone()),
+        StorageKey::Milestones(r.clone(), e.clone()),
+        StorageKey::Stream(r.clone(), e.clone()),
+        StorageKey::PaymentSchedule(r.clone(), e.clone()),
+        StorageKey::KeeperFund(r.clone(), e.clone()),
+        StorageKey::Bond(r.clone(), e.clone()),
+        StorageKey::Pool(r.clone(), e.clone()),
+        StorageKey::PoolMembership(r.clone(), e.clone()),
+        StorageKey::Contributions(r.clone(), e.clone()),
+        StorageKey::RefundRule(r, e),
+    ];
+    keys.iter().any(|key| env.storage().persistent().has(key))
+}
+
+// Moves everything scoped to the (retainor, retainee) pair over to a new key pair
+fn move_engagement(env: &Env, retainor: &Address, retainee: &Address, new_retainor: &Address, new_retainee: &Address) {
+    let (r, e, nr, ne) = (retainor.clone(), retainee.clone(), new_retainor.clone(), new_retainee.clone());
+    let index = get_history_index(env, retainor, retainee);
+    for i in 1..=index {
+        move_entry::<Receipt>(env, StorageKey::History(r.clone(), e.clone(), i), StorageKey::History(nr.clone(), ne.clone(), i));
+        move_entry::<i128>(env, StorageKey::Credited(r.clone(), e.clone(), i), StorageKey::Credited(nr.clone(), ne.clone(), i));
+        move_entry::<ReasonCode>(env, StorageKey::ReceiptReason(r.clone(), e.clone(), i), StorageKey::ReceiptReason(nr.clone(), ne.clone(), i));
+    }
+    for year in get_withholding_years(env, retainor, retainee).iter() {
+        move_entry::<i128>(env, StorageKey::WithheldInYear(r.clone(), e.clone(), year), StorageKey::WithheldInYear(nr.clone(), ne.clone(), year));
+    }
+    move_entry::<Vec<u32>>(env, StorageKey::WithholdingYears(r.clone(), e.clone()), StorageKey::WithholdingYears(nr.clone(), ne.clone()));
+    move_entry::<u32>(env, StorageKey::HistoryIndex(r.clone(), e.clone()), StorageKey::HistoryIndex(nr.clone(), ne.clone()));
+    move_entry::<RetainerBalance>(env, StorageKey::Retainer(r.clone(), e.clone()), StorageKey::Retainer(nr.clone(), ne.clone()));
+    move_entry::<EngagementStats>(env, StorageKey::EngagementStats(r.clone(), e.clone()), StorageKey::EngagementStats(nr.clone(), ne.clone()));
+    move_entry::<ApprovalPolicy>(env, StorageKey::ApprovalPolicy(r.clone(), e.clone()), StorageKey::ApprovalPolicy(nr.clone(), ne.clone()));
+    move_entry::<Vec<AutoApproval>>(env, StorageKey::AutoApprovals(r.clone(), e.clone()), StorageKey::AutoApprovals(nr.clone(), ne.clone()));
+    move_entry::<BillingCap>(env, StorageKey::BillingCap(r.clone(), e.clone()), StorageKey::BillingCap(nr.clone(), ne.clone()));
+    move_entry::<PeriodSpend>(env, StorageKey::PeriodSpend(r.clone(), e.clone()), StorageKey::PeriodSpend(nr.clone(), ne.clone()));
+    move_entry::<Vec<Rate>>(env, StorageKey::RateCard(r.clone(), e.clone()), StorageKey::RateCard(nr.clone(), ne.clone()));
+    move_entry::<Budget>(env, StorageKey::Budget(r.clone(), e.clone()), StorageKey::Budget(nr.clone(), ne.clone()));
+    move_entry::<u64>(env, StorageKey::Expiry(r.clone(), e.clone()), StorageKey::Expiry(nr.clone(), ne.clone()));
+    move_entry::<i128>(env, StorageKey::EvergreenTarget(r.clone(), e.clone()), StorageKey::EvergreenTarget(nr.clone(), ne.clone()));
+    move_entry::<Address>(env, StorageKey::AllowanceFunded(r.clone(), e.clone()), StorageKey::AllowanceFunded(nr.clone(), ne.clone()));
+    move_entry::<Vec<SplitShare>>(env, StorageKey::PayoutSplit(r.clone(), e.clone()), StorageKey::PayoutSplit(nr.clone(), ne.clone()));
+    move_entry::<Address>(env, StorageKey::PayoutAddress(r.clone(), e.clone()), StorageKey::PayoutAddress(nr.clone(), ne.clone()));
+    move_entry::<PayoutAddressChange>(env, StorageKey::PayoutAddressChange(r.clone(), e.clone()), StorageKey::PayoutAddressChange(nr.clone(), ne.clone()));
+    move_entry::<PayoutSplitChange>(env, StorageKey::PayoutSplitChange(r.clone(), e.clone()), StorageKey::PayoutSplitChange(nr.clone(), ne.clone()));
+    move_entry::<Withholding>(env, StorageKey::Withholding(r.clone(), e.clone()), StorageKey::Withholding(nr.clone(), ne.clone()));
+    move_entry::<Vec<Milestone>>(env, StorageKey::Milestones(r.clone(), e.clone()), StorageKey::Milestones(nr.clone(), ne.clone()));
+    move_entry::<Stream>(env, StorageKey::Stream(r.clone(), e.clone()), StorageKey::Stream(nr.clone(), ne.clone()));
+    move_entry::<PaymentSchedule>(env, StorageKey::PaymentSchedule(r.clone(), e.clone()), StorageKey::PaymentSchedule(nr.clone(), ne.clone()));
+    move_entry::<KeeperFund>(env, StorageKey::KeeperFund(r.clone(), e.clone()), StorageKey::KeeperFund(nr.clone(), ne.clone()));
+    move_entry::<Bond>(env, StorageKey::Bond(r.clone(), e.clone()), StorageKey::Bond(nr.clone(), ne.clone()));
+    move_entry::<Vec<Contribution>>(env, StorageKey::Contributions(r.clone(), e.clone()), StorageKey::Contributions(nr.clone(), ne.clone()));
+    move_entry::<RefundRule>(env, StorageKey::RefundRule(r, e), StorageKey::RefundRule(nr, ne));
+}
+
+// Replaces `old` with `new` in a directory list, appending `new` if `old` was not listed
+fn replace_in_directory(list: &mut Vec<Address>, old: &Address, new: &Address) {
+    let listed = list.contains(new);
+    match list.first_index_of(old) {
+        Some(i) if listed => {
+            list.remove(i);
+        }
+        Some(i) => list.set(i, new.clone()),
+        None if !listed => list.push_back(new.clone()),
+        None => {}
+    }
+}
+
+// Drops `retainor` from the old retainee's directory entry and lists it under the new retainee
+fn move_retainee_directory(env: &Env, retainor: &Address, retainee: &Address, new_retainee: &Address) {
+    let name = match get_retainee_info(env, retainee) {
+        Some(mut info) => {
+            if let Some(i) = info.retainors.first_index_of(retainor) {
+                info.retainors.remove(i);
+            }
+            let name = info.name.clone();
+            set_retainee_info(env, retainee, info);
+            name
+        }
+        None => String::from_str(env, ""),
+    };
+    let mut info = get_retainee_info(env, new_retainee).unwrap_or(RetaineeInfo {
+        name,
+        retainors: Vec::new(env),
+    });
+    if !info.retainors.contains(retainor) {
+        info.retainors.push_back(retainor.clone());
+    }
+    set_retainee_info(env, new_retainee, info);
+}
+
+// Drops `retainee` from the old retainor's directory entry and lists it under the new retainor
+fn move_retainor_directory(env: &Env, retainor: &Address, retainee: &Address, new_retainor: &Address) {
+    let name = match get_retainor_info(env, retainor) {
+        Some(mut info) => {
+            if let Some(i) = info.retainees.first_index_of(retainee) {
+                info.retainees.remove(i);
+            }
+            let name = info.name.clone();
+            set_retainor_info(env, retainor, info);
+            name
+        }
+        None => String::from_str(env, ""),
+    };
+    let mut info = get_retainor_info(env, new_retainor).unwrap_or(RetainorInfo {
+        name,
+        retainees: Vec::new(env),
+    });
+    if !info.retainees.contains(retainee) {
+        info.retainees.push_back(retainee.clone());
+    }
+    set_retainor_info(env, new_retainor, info);
+}
+
+fn get_pool_members(env: &Env, retainor: &Address, pool: &Address) -> Vec<PoolMember> {
+    env.storage()
+        .persistent()
+        .get::<_, Vec<PoolMember>>(&StorageKey::Pool(retainor.clone(), pool.clone()))
+        .unwrap_or(Vec::new(env))
+}
+
+fn set_pool_members(env: &Env, retainor: &Address, pool: &Address, members: Vec<PoolMember>) {
+    env.storage()
+        .persistent()
+        .set::<_, Vec<PoolMember>>(&StorageKey::Pool(retainor.clone(), pool.clone()), &members);
+}
+
+fn get_member_pool(env: &Env, retainor: &Address, member: &Address) -> Option<Address> {
+    env.storage()
+        .persistent()
+        .get::<_, Address>(&StorageKey::PoolMembership(retainor.clone(), member.clone()))
+}
+
+// Pool members bill against the pool's balance, everyone else against their own
+fn get_funding_retainee(env: &Env, retainor: &Address, retainee: &Address) -> Address {
+    get_member_pool(env, retainor, retainee).unwrap_or(retainee.clone())
+}
+
+fn check_member_limit(env: &Env, retainor: &Address, retainee: &Address, amount: i128) {
+    if let Some(pool) = get_member_pool(env, retainor, retainee) {
+        for member in get_pool_members(env, retainor, &pool).iter() {
+            if member.member == *retainee {
+                if let Some(limit) = member.limit {
+                    if member.spent.checked_add(amount).unwrap() > limit {
+                        panic!("Exceeds member limit");
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn record_member_spend(env: &Env, retainor: &Address, retainee: &Address, amount: i128) {
+    if let Some(pool) = get_member_pool(env, retainor, retainee) {
+        let mut members = get_pool_members(env, retainor, &pool);
+        for (i, mut member) in members.iter().enumerate() {
+            if member.member == *retainee {
+                member.spent = member.spent.checked_add(amount).unwrap();
+                members.set(i as u32, member);
+            }
+        }
+        set_pool_members(env, retainor, &pool, members);
+    }
+}
+
+fn get_contributions(env: &Env, retainor: &Address, retainee: &Address) -> Vec<Contribution> {
+    env.storage()
+        .persistent()
+        .get::<_, Vec<Contribution>>(&StorageKey::Contributions(retainor.clone(), retainee.clone()))
+        .unwrap_or(Vec::new(env))
+}
+
+fn set_contributions(env: &Env, retainor: &Address, retainee: &Address, contributions: Vec<Contribution>) {
+    env.storage()
+        .persistent()
+        .set::<_, Vec<Contribution>>(&StorageKey::Contributions(retainor.clone(), retainee.clone()), &contributions);
+}
+
+fn record_contribution(env: &Env, retainor: &Address, retainee: &Address, funder: &Address, amount: i128) {
+    let mut contributions = get_contributions(env, retainor, retainee);
+    match contributions.iter().position(|c| c.funder == *funder) {
+        Some(i) => {
+            let mut contribution = contributions.get(i as u32).unwrap();
+            contribution.amount = contribution.amount.checked_add(amount).unwrap();
+            contributions.set(i as u32, contribution);
+        }
+        None => contributions.push_back(Contribution {
+            funder: funder.clone(),
+            amount,
+        }),
+    }
+    set_contributions(env, retainor, retainee, contributions);
+}
+
+// Payouts draw on every funder's money pro rata, so a refund only covers what is still escrowed
+fn spend_contributions(env: &Env, retainor: &Address, retainee: &Address, amount: i128) {
+    let mut contributions = get_contributions(env, retainor, retainee);
+    let total = contributions.iter().fold(0i128, |sum, c| sum.checked_add(c.amount).unwrap());
+    if total <= 0 {
+        return;
+    }
+    let mut remaining = amount.min(total);
+    let spent = remaining;
+    for (i, mut contribution) in contributions.iter().enumerate() {
+        let share = spent.checked_mul(contribution.amount).unwrap().checked_div(total).unwrap();
+        contribution.amount = contribution.amount.checked_sub(share).unwrap();
+        remaining = remaining.checked_sub(share).unwrap();
+        contributions.set(i as u32, contribution);
+    }
+    // rounding dust comes off whoever still has something left
+    for (i, mut contribution) in contributions.iter().enumerate() {
+        if remaining == 0 {
+            break;
+        }
+        let share = remaining.min(contribution.amount);
+        contribution.amount = contribution.amount.checked_sub(share).unwrap();
+        remaining = remaining.checked_sub(share).unwrap();
+        contributions.set(i as u32, contribution);
+    }
+    set_contributions(env, retainor, retainee, contributions);
+}
+
+fn has_sponsors(env: &Env, retainor: &Address, retainee: &Address) -> bool {
+    get_contributions(env, retainor, retainee)
+        .iter()
+        .any(|c| c.funder != *retainor && c.amount > 0)
+}
+
+fn get_refund_rule(env: &Env, retainor: &Address, retainee: &Address) -> RefundRule {
+    env.storage()
+        .persistent()
+        .get::<_, RefundRule>(&StorageKey::RefundRule(retainor.clone(), retainee.clone()))
+        .unwrap_or(RefundRule::ProRata)
+}
+
+// Splits a refund between funders per the engagement's RefundRule, reducing their contributions.
+// Anything not owed to a sponsor goes back to the retainor.
+fn split_refund(env: &Env, retainor: &Address, retainee: &Address, amount: i128) -> Vec<Payout> {
+    let mut contributions = get_contributions(env, retainor, retainee);
+    let mut refunds = Vec::new(env);
+    let mut remaining = amount;
+    match get_refund_rule(env, retainor, retainee) {
+        RefundRule::ProRata => {
+            let total = contributions.iter().fold(0i128, |sum, c| sum.checked_add(c.amount).unwrap());
+            if total > 0 {
+                for (i, mut contribution) in contributions.iter().enumerate() {
+                    let share = amount
+                        .checked_mul(contribution.amount)
+                        .unwrap()
+                        .checked_div(total)
+                        .unwrap()
+                        .min(contribution.amount);
+                    contribution.amount = contribution.amount.checked_sub(share).unwrap();
+                    remaining = remaining.checked_sub(share).unwrap();
+                    contributions.set(i as u32, contribution.clone());
+                    refunds.push_back(Payout {
+                        recipient: contribution.funder,
+                        amount: share,
+                    });
+                }
+            }
+        }
+        RefundRule::SponsorsFirst => {
+            for (i, mut contribution) in contributions.iter().enumerate() {
+                if contribution.funder == *retainor {
+                    continue;
+                }
+                let share = remaining.min(contribution.amount);
+                contribution.amount = contribution.amount.checked_sub(share).unwrap();
+                remaining = remaining.checked_sub(share).unwrap();
+                contributions.set(i as u32, contribution.clone());
+                refunds.push_back(Payout {
+                    recipient: contribution.funder,
+                    amount: share,
+                });
+            }
+        }
+    }
+    // rounding dust and funds beyond recorded contributions belong to the retainor
+    if remaining > 0 {
+        if let Some(i) = contributions.iter().position(|c| c.funder == *retainor) {
+            let mut contribution = contributions.get(i as u32).unwrap();
+            contribution.amount = contribution.amount.checked_sub(remaining).unwrap().max(0);
+            contributions.set(i as u32, contribution);
+        }
+        refunds.push_back(Payout {
+            recipient: retainor.clone(),
+            amount: remaining,
+        });
+    }
+    set_contributions(env, retainor, retainee, contributions);
+    refunds
+}
+
+fn get_receipt_reason(env: &Env, retainor: &Address, retainee: &Address, index: u32) -> Option<ReasonCode> {
+    env.storage()
+        .persistent()
+        .get::<_, ReasonCode>(&StorageKey::ReceiptReason(retainor.clone(), retainee.clone(), index))
+}
+
+fn set_receipt_reason(env: &Env, retainor: &Address, retainee: &Address, index: u32, reason: ReasonCode) {
+    env.storage()
+        .persistent()
+        .set::<_, ReasonCode>(&StorageKey::ReceiptReason(retainor.clone(), retainee.clone(), index), &reason);
+}
+
+fn set_credited(env: &Env, retainor: &Address, retainee: &Address, index: u32, amount: i128) {
+    env.storage()
+        .persistent()
+        .set::<_, i128>(&StorageKey::Credited(retainor.clone(), retainee.clone(), index), &amount);
+}
+
+// Appends a receipt to the engagement history and returns its index
+fn push_receipt(env: &Env, retainor: &Address, retainee: &Address, receipt: Receipt) -> u32 {
+    push_receipt_with_reason(env, retainor, retainee, receipt, None)
+}
+
+// Like push_receipt, also recording the reason given. A pool member's receipts are copied
+// into the pool's history too, so the pool shows every bill drawn from it and who billed.
+fn push_receipt_with_reason(env: &Env, retainor: &Address, retainee: &Address, mut receipt: Receipt, reason: Option<ReasonCode>) -> u32 {
+    if let Some(pool) = get_member_pool(env, retainor, retainee) {
+        receipt.member = Some(retainee.clone());
+        append_receipt(env, retainor, &pool, receipt.clone(), reason.clone());
+    }
+    append_receipt(env, retainor, retainee, receipt, reason)
+}
+
+fn append_receipt(env: &Env, retainor: &Address, retainee: &Address, receipt: Receipt, reason: Option<ReasonCode>) -> u32 {
+    let index = get_history_index(env, retainor, retainee) + 1;
+    set_receipt(env, retainor, retainee, index, receipt);
+    set_history_index(env, retainor, retainee, index);
+    if let Some(reason) = reason {
+        set_receipt_reason(env, retainor, retainee, index, reason);
+    }
+    index
+}
+
+fn set_stats(env: &Env, key: &StorageKey, stats: EngagementStats) {
+    env.storage()
+        .persistent()
+        .set::<_, EngagementStats>(key, &stats);
+}
+
+// Applies the same change to the engagement counters and to both parties' per-token counters
+fn update_stats(env: &Env, retainor: &Address, retainee: &Address, token: &Address, update: impl Fn(&mut EngagementStats)) {
+    let keys = [
+        StorageKey::EngagementStats(retainor.clone(), retainee.clone()),
+        StorageKey::RetainorStats(retainor.clone(), token.clone()),
+        StorageKey::RetaineeStats(retainee.clone(), token.clone()),
+    ];
+    for key in keys.iter() {
+        let mut stats = get_stats(env, key);
+        update(&mut stats);
+        set_stats(env, key, stats);
+    }
+}
+
+// Transfers an approved amount out of escrow to the retainee, or across their payout split,
+// less any tax withholding, and returns the payouts made and the amount withheld
+fn pay_from_retainer(env: &Env, retainor: &Address, retainee: &Address, token: &Address, amount: i128) -> (Vec<Payout>, i128) {
+    let funding = get_funding_retainee(env, retainor, retainee);
+    record_period_spend(env, retainor, retainee, amount);
+    if funding != *retainee {
+        record_period_spend(env, retainor, &funding, amount);
+    }
+    pay_uncapped(env, retainor, retainee, token, amount)
+}
+
+// Pays out like pay_from_retainer without counting against the billing cap
+fn pay_uncapped(env: &Env, retainor: &Address, retainee: &Address, token: &Address, amount: i128) -> (Vec<Payout>, i128) {
+    let funding = get_funding_retainee(env, retainor, retainee);
+    record_budget_spend(env, retainor, retainee, amount);
+    let withholding = get_withholding(env, retainor, retainee);
+    let withheld = match &withholding {
+        Some(withholding) => amount.checked_mul(withholding.rate_bps as i128).unwrap() / 10_000,
+        None => 0,
+    };
+    let net_amount = amount.checked_sub(withheld).unwrap();
+    let payouts = match get_payout_split(env, retainor, retainee) {
+        Some(split) => split_amount(env, &split, net_amount),
+        None => {
+            let payout_address = get_payout_address(env, retainor, retainee);
+            if payout_address == *retainee {
+                Vec::new(env)
+            } else {
+                vec![env, Payout {
+                    recipient: payout_address,
+                    amount: net_amount,
+                }]
+            }
+        }
+    };
+    let mut transfers = payouts.clone();
+    if transfers.is_empty() {
+        transfers.push_back(Payout {
+            recipient: retainee.clone(),
+            amount: net_amount,
+        });
+    }
+    if withheld > 0 {
+        transfers.push_back(Payout {
+            recipient: withholding.unwrap().destination,
+            amount: withheld,
+        });
+        record_withheld(env, retainor, retainee, withheld);
+    }
+    let client = token::Client::new(env, token);
+    if get_allowance_token(env, retainor, retainee).is_some() {
+        if get_available_allowance(env, retainor, token) < amount {
+            panic!("Insufficient allowance");
+        }
+        // pay directly from the retainor, nothing is escrowed
+        for transfer in transfers.iter() {
+            client.transfer_from(&env.current_contract_address(), retainor, &transfer.recipient, &transfer.amount);
+        }
+        return (payouts, withheld);
+    }
+    let mut retainer_balance = get_retainer_balance(env, retainor, &funding).unwrap();
+    if retainer_balance.amount < amount {
+        panic!("Insufficient retained balance");
+    }
+    // send payment
+    for transfer in transfers.iter() {
+        client.transfer(&env.current_contract_address(), &transfer.recipient, &transfer.amount);
+    }
+    // update retained balance
+    retainer_balance.amount = retainer_balance.amount.checked_sub(amount).unwrap();
+    set_retainer_balance(env, retainor, &funding, retainer_balance);
+    spend_contributions(env, retainor, &funding, amount);
+    record_member_spend(env, retainor, retainee, amount);
+    replenish(env, retainor, &funding);
+    (payouts, withheld)
+}
+
+// Returns escrowed funds to the retainor and any sponsors who funded the engagement
+fn refund_to_funders(env: &Env, retainor: &Address, retainee: &Address, amount: i128) {
+    let mut retainer_balance = get_retainer_balance(env, retainor, retainee).unwrap();
+    if retainer_balance.amount < amount {
+        panic!("Insufficient retained balance");
+    }
+    retainer_balance.amount = retainer_balance.amount.checked_sub(amount).unwrap();
+    // transfer tokens to funders
+    let client = token::Client::new(env, &retainer_balance.token);
+    for refund in split_refund(env, retainor, retainee, amount).iter() {
+        if refund.amount > 0 {
+            client.transfer(&env.current_contract_address(), &refund.recipient, &refund.amount);
+        }
+    }
+    // update state
+    update_stats(env, retainor, retainee, &retainer_balance.token, |stats| {
+        stats.amount_withdrawn = stats.amount_withdrawn.checked_add(amount).unwrap();
+    });
+    set_retainer_balance(env, retainor, retainee, retainer_balance);
+}
+
+// Closes out a bill left pending on an expired retainer with an Expired receipt
+fn expire_pending_payment(env: &Env, retainor: &Address, retainee: &Address) {
+    let bill = match get_pending_payment(env, retainor, retainee) {
+        Some(bill) => bill,
+        None => return,
+    };
+    let empty = String::from_str(env, "");
+    let receipt = Receipt {
+        bill,
+        notes: empty.clone(),
+        date: empty,
+        status: ApprovalStatus::Expired,
+        settled_amount: 0,
+        withheld: 0,
+        related_receipt: None,
+        payouts: Vec::new(env),
+        member: None,
+    };
+    push_receipt(env, retainor, retainee, receipt);
+    clear_pending_payment(env, retainor, retainee);
+}
+
+fn clear_pending_payment(env: &Env, retainor: &Address, retainee: &Address) {
+    env.storage()
+        .persistent()
+        .remove(&StorageKey::PendingPayment(retainor.clone(), retainee.clone()));
+    env.storage()
+        .persistent()
+        .remove(&StorageKey::CounterOffer(retainor.clone(), retainee.clone()));
+}
+
+// Validates a bill and either pays it under the standing approval policy or leaves it pending
+fn submit(env: &Env, retainor: &Address, retainee: &Address, amount: i128, notes: String, date: String) {
+    check_positive_amount(amount);
+    // pool members are also bound by the pool's expiry, bond and cap
+    let funding = get_funding_retainee(env, retainor, retainee);
+    if is_expired(env, retainor, retainee) || is_expired(env, retainor, &funding) {
+        panic!("Retainer expired");
+    }
+    if get_pending_payment(env, retainor, retainee).is_some() {
+        panic!("Pending payment already exists");
+    }
+    for bond in [get_bond(env, retainor, retainee), get_bond(env, retainor, &funding)].into_iter().flatten() {
+        if bond.posted < bond.required {
+            panic!("Bond not posted");
+        }
+    }
+    let token = match get_allowance_token(env, retainor, retainee) {
+        Some(token) => {
+            if get_available_allowance(env, retainor, &token) < amount {
+                panic!("Insufficient allowance");
+            }
+            token
+        }
+        None => {
+            let retained_balance = match get_retainer_balance(env, retainor, &funding) {
+                Some(balance) => balance,
+                None => panic!("No retained balance"),
+            };
+            check_member_limit(env, retainor, retainee, amount);
+            // funds reserved for milestones can't be billed
+            let committed = get_committed(env, retainor, &funding);
+            if retained_balance.amount.checked_sub(committed).unwrap() < amount {
+                panic!("Insufficient retained balance");
+            }
+            retained_balance.token
+        }
+    };
+    check_cap_headroom(env, retainor, retainee, amount);
+    if funding != *retainee {
+        check_cap_headroom(env, retainor, &funding, amount);
+    }
+    let bill = Bill {
+        amount,
+        notes,
+        date,
+        token: token.clone(),
+    };
+    update_stats(env, retainor, retainee, &token, |stats| {
+        stats.bills_submitted += 1;
+    });
+    check_budget_thresholds(env, retainor, retainee, amount);
+    if !exceeds_budget(env, retainor, retainee, amount) && try_auto_approve(env, retainor, retainee, amount) {
+        let (payouts, withheld) = pay_from_retainer(env, retainor, retainee, &bill.token, amount);
+        update_stats(env, retainor, retainee, &bill.token, |stats| {
+            stats.bills_approved += 1;
+            stats.amount_paid = stats.amount_paid.checked_add(amount).unwrap();
+        });
+        let receipt = Receipt {
+            notes: bill.notes.clone(),
+            date: bill.date.clone(),
+            bill,
+            status: ApprovalStatus::AutoApproved,
+            settled_amount: amount,
+            withheld,
+            related_receipt: None,
+            payouts,
+            member: None,
+        };
+        push_receipt(env, retainor, retainee, receipt);
+    } else {
+        set_pending_payment(env, retainor, retainee, bill);
+    }
+}
+
+fn get_signing_key(env: &Env, retainor: &Address) -> Option<BytesN<32>> {
+    env.storage()
+        .persistent()
+        .get::<_, BytesN<32>>(&StorageKey::SigningKey(retainor.clone()))
+}
+
+fn get_signer_nonce(env: &Env, retainor: &Address) -> u64 {
+    env.storage()
+        .persistent()
+        .get::<_, u64>(&StorageKey::SignerNonce(retainor.clone()))
+        .unwrap_or(0)
+}
+
+fn set_signer_nonce(env: &Env, retainor: &Address, nonce: u64) {
+    env.storage()
+        .persistent()
+        .set::<_, u64>(&StorageKey::SignerNonce(retainor.clone()), &nonce);
+}
+
+fn hash_bill(env: &Env, bill: &Bill) -> BytesN<32> {
+    env.crypto().sha256(&bill.clone().to_xdr(env)).into()
+}
+
+// Binds a signed approval and its reason to this contract so neither can be swapped by the
+// relayer or replayed against another deployment. The reason travels outside SignedApproval
+// because contracttype structs can't hold an Option of a contracttype enum under testutils.
+fn approval_message(env: &Env, approval: &SignedApproval, reason: &Option<ReasonCode>) -> Bytes {
+    (env.current_contract_address(), approval.clone(), reason.clone()).to_xdr(env)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn resolve(env: &Env, retainor: &Address, retainee: &Address, status: ApprovalStatus, reason: Option<ReasonCode>, over_budget: bool, notes: String, date: String) {
+    let bill = match get_pending_payment(env, retainor, retainee) {
+        Some(bill) => bill,
+        None => panic!("No pending payment"),
+    };
+    match (&status, &reason) {
+        (ApprovalStatus::Approved, None) => {}
+        (ApprovalStatus::Approved, _) => panic!("Reason not allowed on approval"),
+        (ApprovalStatus::Denied, None) => panic!("Reason required"),
+        (ApprovalStatus::Denied, _) => {}
+        _ => panic!("Invalid status"),
+    }
+    let settled_amount = if status == ApprovalStatus::Approved { bill.amount } else { 0 };
+    let mut receipt = Receipt {
+        bill: bill.clone(),
+        notes,
+        date,
+        status: status.clone(),
+        settled_amount,
+        withheld: 0,
+        related_receipt: None,
+        payouts: Vec::new(env),
+        member: None,
+    };
+    if status == ApprovalStatus::Approved {
+        if !over_budget && exceeds_budget(env, retainor, retainee, bill.amount) {
+            panic!("Budget exceeded");
+        }
+        let (payouts, withheld) = pay_from_retainer(env, retainor, retainee, &bill.token, bill.amount);
+        receipt.payouts = payouts;
+        receipt.withheld = withheld;
+        update_stats(env, retainor, retainee, &bill.token, |stats| {
+            stats.bills_approved += 1;
+            stats.amount_paid = stats.amount_paid.checked_add(bill.amount).unwrap();
+        });
+    }
+    if let Some(reason) = &reason {
+        update_stats(env, retainor, retainee, &bill.token, |stats| {
+            stats.bills_denied += 1;
+            count_reason(&mut stats.reasons, reason);
+        });
+    }
+    push_receipt_with_reason(env, retainor, retainee, receipt, reason);
+    clear_pending_payment(env, retainor, retainee);
+}
+
+#[contract]
+pub struct Contract;
+
+#[contractimpl]
+impl Contract {
+
+    pub fn submit_bill(env: Env, retainor: Address, retainee: Address, amount: i128, notes: String, date: String) {
+        retainee.require_auth();
+        submit(&env, &retainor, &retainee, amount, notes, date);
+    }
+
+    pub fn submit_time_bill(env: Env, retainor: Address, retainee: Address, entries: Vec<TimeEntry>, notes: String, date: String) {
+        retainee.require_auth();
+        let amount = compute_time_bill(&env, &retainor, &retainee, &entries);
+        submit(&env, &retainor, &retainee, amount, notes, date);
+    }
+
+    pub fn set_rate(env: Env, retainor: Address, retainee: Address, code: Symbol, rate: i128, effective_from: u64) {
+        retainor.require_auth();
+        retainee.require_auth();
+        check_positive_amount(rate);
+        let mut rate_card = get_rate_card(&env, &retainor, &retainee);
+        // a second change for the same code and time replaces the first
+        if let Some(index) = rate_card.iter().position(|r| r.code == code && r.effective_from == effective_from) {
+            rate_card.remove(index as u32);
+        }
+        rate_card.push_back(Rate {
+            code,
+            rate,
+            effective_from,
+        });
+        set_rate_card(&env, &retainor, &retainee, rate_card);
+    }
+
+    pub fn rate_card(env: Env, retainor: Address, retainee: Address) -> Vec<Rate> {
+        get_rate_card(&env, &retainor, &retainee)
+    }
+
+    pub fn current_rate(env: Env, retainor: Address, retainee: Address, code: Symbol) -> Option<i128> {
+        get_current_rate(&env, &retainor, &retainee, &code)
+    }
+
+    pub fn set_approval_policy(env: Env, retainor: Address, retainee: Address, max_per_bill: i128, max_per_period: i128, period: u64) {
+        retainor.require_auth();
+        check_positive_amount(max_per_bill);
+        check_positive_amount(max_per_period);
+        if max_per_bill > max_per_period {
+            panic!("Bill limit exceeds period limit");
+        }
+        if period == 0 {
+            panic!("Invalid period");
+        }
+        set_approval_policy(&env, &retainor, &retainee, ApprovalPolicy {
+            max_per_bill,
+            max_per_period,
+            period,
+        });
+    }
+
+    pub fn remove_approval_policy(env: Env, retainor: Address, retainee: Address) {
+        retainor.require_auth();
+        clear_approval_policy(&env, &retainor, &retainee);
+    }
+
+    pub fn approval_policy(env: Env, retainor: Address, retainee: Address) -> Option<ApprovalPolicy> {
+        get_approval_policy(&env, &retainor, &retainee)
+    }
+
+    pub fn unsubmit_bill(env: Env, retainor: Address, retainee: Address, notes: String, date: String) {
+        retainee.require_auth();
+        let bill = match get_pending_payment(&env, &retainor, &retainee) {
+            Some(bill) => bill,
+            None => panic!("No pending payment"),
+        };
+        update_stats(&env, &retainor, &retainee, &bill.token, |stats| {
+            stats.bills_withdrawn += 1;
+        });
+        let receipt = Receipt {
+            bill,
+            notes,
+            date,
+            status: ApprovalStatus::Withdrawn,
+            settled_amount: 0,
+            withheld: 0,
+            related_receipt: None,
+            payouts: Vec::new(&env),
+            member: None,
+        };
+        push_receipt(&env, &retainor, &retainee, receipt);
+        clear_pending_payment(&env, &retainor, &retainee);
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn resolve_bill(env: Env, retainor: Address, retainee: Address, status: ApprovalStatus, reason: Option<ReasonCode>, over_budget: bool, notes: String, date: String) {
+        retainor.require_auth();
+        resolve(&env, &retainor, &retainee, status, reason, over_budget, notes, date);
+    }
+
+    // Resolves a bill with an approval the retainor signed off-chain, submitted by any relayer.
+    // Budget overrides still need the retainor to call resolve_bill directly.
+    pub fn resolve_bill_signed(env: Env, approval: SignedApproval, reason: Option<ReasonCode>, signature: BytesN<64>) {
+        let public_key = match get_signing_key(&env, &approval.retainor) {
+            Some(public_key) => public_key,
+            None => panic!("No signing key"),
+        };
+        if env.ledger().timestamp() > approval.expiry {
+            panic!("Approval expired");
+        }
+        let nonce = get_signer_nonce(&env, &approval.retainor);
+        if approval.nonce != nonce {
+            panic!("Invalid nonce");
+        }
+        let bill = match get_pending_payment(&env, &approval.retainor, &approval.retainee) {
+            Some(bill) => bill,
+            None => panic!("No pending payment"),
+        };
+        if approval.bill_hash != hash_bill(&env, &bill) {
+            panic!("Bill hash mismatch");
+        }
+        env.crypto().ed25519_verify(&public_key, &approval_message(&env, &approval, &reason), &signature);
+        set_signer_nonce(&env, &approval.retainor, nonce + 1);
+        resolve(&env, &approval.retainor, &approval.retainee, approval.status, reason, false, approval.notes, approval.date);
+    }
+
+    pub fn set_signing_key(env: Env, retainor: Address, public_key: BytesN<32>) {
+        retainor.require_auth();
+        env.storage()
+            .persistent()
+            .set::<_, BytesN<32>>(&StorageKey::SigningKey(retainor.clone()), &public_key);
+    }
+
+    pub fn remove_signing_key(env: Env, retainor: Address) {
+        retainor.require_auth();
+        env.storage()
+            .persistent()
+            .remove(&StorageKey::SigningKey(retainor.clone()));
+    }
+
+    pub fn signing_key(env: Env, retainor: Address) -> Option<BytesN<32>> {
+        get_signing_key(&env, &retainor)
+    }
+
+    pub fn signer_nonce(env: Env, retainor: Address) -> u64 {
+        get_signer_nonce(&env, &retainor)
+    }
+
+    pub fn bill_hash(env: Env, retainor: Address, retainee: Address) -> BytesN<32> {
+        match get_pending_payment(&env, &retainor, &retainee) {
+            Some(bill) => hash_bill(&env, &bill),
+            None => panic!("No pending payment"),
+        }
+    }
+
+    // Bytes the retainor signs for a SignedApproval
+    pub fn approval_message(env: Env, approval: SignedApproval, reason: Option<ReasonCode>) -> Bytes {
+        approval_message(&env, &approval, &reason)
+    }
+
+    pub fn counter_bill(env: Env, retainor: Address, retainee: Address, amount: i128, reason: ReasonCode, notes: String, date: String) {
+        retainor.require_auth();
+        check_positive_amount(amount);
+        let bill = match get_pending_payment(&env, &retainor, &retainee) {
+            Some(bill) => bill,
+            None => panic!("No pending payment"),
+        };
+        if amount >= bill.amount {
+            panic!("Counter must be lower than bill");
+        }
+        set_counter_offer(&env, &retainor, &retainee, CounterOffer {
+            amount,
+            reason,
+            notes,
+            date,
+        });
+    }
+
+    pub fn accept_counter(env: Env, retainor: Address, retainee: Address, date: String) {
+        retainee.require_auth();
+        let bill = match get_pending_payment(&env, &retainor, &retainee) {
+            Some(bill) => bill,
+            None => panic!("No pending payment"),
+        };
+        let offer = match get_counter_offer(&env, &retainor, &retainee) {
+            Some(offer) => offer,
+            None => panic!("No counter offer"),
+        };
+        if exceeds_budget(&env, &retainor, &retainee, offer.amount) {
+            panic!("Budget exceeded");
+        }
+        let (payouts, withheld) = pay_from_retainer(&env, &retainor, &retainee, &bill.token, offer.amount);
+        update_stats(&env, &retainor, &retainee, &bill.token, |stats| {
+            stats.bills_countered += 1;
+            stats.amount_paid = stats.amount_paid.checked_add(offer.amount).unwrap();
+            count_reason(&mut stats.reasons, &offer.reason);
+        });
+        let receipt = Receipt {
+            bill,
+            notes: offer.notes,
+            date,
+            status: ApprovalStatus::Countered,
+            settled_amount: offer.amount,
+            withheld,
+            related_receipt: None,
+            payouts,
+            member: None,
+        };
+        push_receipt_with_reason(&env, &retainor, &retainee, receipt, Some(offer.reason));
+        clear_pending_payment(&env, &retainor, &retainee);
+    }
+
+    pub fn view_counter(env: Env, retainor: Address, retainee: Address) -> Option<CounterOffer> {
+        get_counter_offer(&env, &retainor, &retainee)
+    }
+
+    pub fn issue_credit(env: Env, retainor: Address, retainee: Address, amount: i128, receipt_index: u32, notes: String, date: String) {
+        retainee.require_auth();
+        check_positive_amount(amount);
+        let receipt = match get_receipt(&env, &retainor, &retainee, receipt_index) {
+            Some(receipt) => receipt,
+            None => panic!("No such receipt"),
+        };
+        match receipt.status {
+            ApprovalStatus::Approved | ApprovalStatus::AutoApproved | ApprovalStatus::Countered | ApprovalStatus::Milestone => {}
+            _ => panic!("Receipt was not approved"),
+        }
+        let credited = get_credited(&env, &retainor, &retainee, receipt_index).checked_add(amount).unwrap();
+        if credited > receipt.settled_amount {
+            panic!("Credit exceeds billed amount");
+        }
+        if get_allowance_token(&env, &retainor, &retainee).is_some() {
+            // nothing is escrowed, so refund the retainor directly
+            token::Client::new(&env, &receipt.bill.token).transfer(&retainee, &retainor, &amount);
+        } else {
+            let funding = get_funding_retainee(&env, &retainor, &retainee);
+            let mut retainer_balance = get_retainer_balance(&env, &retainor, &funding).unwrap();
+            retainer_balance.amount = retainer_balance.amount.checked_add(amount).unwrap();
+            // transfer tokens back into escrow
+            token::Client::new(&env, &receipt.bill.token).transfer(&retainee, &env.current_contract_address(), &amount);
+            // update state
+            set_retainer_balance(&env, &retainor, &funding, retainer_balance);
+        }
+        set_credited(&env, &retainor, &retainee, receipt_index, credited);
+        update_stats(&env, &retainor, &retainee, &receipt.bill.token, |stats| {
+            stats.amount_credited = stats.amount_credited.checked_add(amount).unwrap();
+        });
+        let credit_note = Receipt {
+            bill: Bill {
+                amount,
+                token: receipt.bill.token.clone(),
+                notes: notes.clone(),
+                date: date.clone(),
+            },
+            notes,
+            date,
+            status: ApprovalStatus::Credited,
+            settled_amount: 0,
+            withheld: 0,
+            related_receipt: Some(receipt_index),
+            payouts: Vec::new(&env),
+            member: None,
+        };
+        push_receipt(&env, &retainor, &retainee, credit_note);
+    }
+
+    pub fn credited_amount(env: Env, retainor: Address, retainee: Address, receipt_index: u32) -> i128 {
+        get_credited(&env, &retainor, &retainee, receipt_index)
+    }
+
+    pub fn view_bill(env: Env, retainor: Address, retainee: Address) -> Option<Bill> {
+        get_pending_payment(&env, &retainor, &retainee)
+    }
+
+    pub fn view_bill_unwrap(env: Env, retainor: Address, retainee: Address) -> Bill {
+        get_pending_payment(&env, &retainor, &retainee).unwrap()
+    }
+
+    pub fn view_receipt(env: Env, retainor: Address, retainee: Address, index: u32) -> Option<Receipt> {
+        get_receipt(&env, &retainor, &retainee, index)
+    }
+
+    // Why the bill behind a receipt was denied or countered, if it was
+    pub fn receipt_reason(env: Env, retainor: Address, retainee: Address, index: u32) -> Option<ReasonCode> {
+        get_receipt_reason(&env, &retainor, &retainee, index)
+    }
+
+    pub fn history_index(env: Env, retainor: Address, retainee: Address) -> u32 {
+        get_history_index(&env, &retainor, &retainee)
+    }
+
+    pub fn view_receipt_history_range(env: Env, retainor: Address, retainee: Address, start: u32, end: u32) -> Vec<Receipt> {
+        let mut history = Vec::new(&env);
+        for i in start..=end {
+            if let Some(receipt) = get_receipt(&env, &retainor, &retainee, i) {
+                history.push_back(receipt);
+            }
+        }
+        history
+    }
+
+    // Reasons are stored beside receipts because a contracttype struct can't hold an Option of a
+    // contracttype enum under testutils, so this pairs each receipt with its reason
+    pub fn view_history_with_reasons(env: Env, retainor: Address, retainee: Address, start: u32, end: u32) -> Vec<(Receipt, Option<ReasonCode>)> {
+        let mut history = Vec::new(&env);
+        for i in start..=end {
+            if let Some(receipt) = get_receipt(&env, &retainor, &retainee, i) {
+                history.push_back((receipt, get_receipt_reason(&env, &retainor, &retainee, i)));
+            }
+        }
+        history
+    }
+
+    pub fn view_receipt_history(env: Env, retainor: Address, retainee: Address, limit: u32) -> Vec<Receipt> {
+        let index = get_history_index(&env, &retainor, &retainee);
+        if index < 1 {
+            return Vec::new(&env);
         }
         if limit > 0 && index > limit {
-            return Self::view_receipt_history_range(
+            Self::view_receipt_history_range(
                 env.clone(),
                 retainor.clone(),
                 retainee.clone(),
                 index - limit + 1,
-                index);
+                index)
         } else {
-            return Self::view_receipt_history_range(
+            Self::view_receipt_history_range(
                 env.clone(),
                 retainor.clone(),
                 retainee.clone(),
                 1,
-                index);
+                index)
+        }
+    }
+
+    pub fn retainer_balance(env: Env, retainor: Address, retainee: Address) -> Option<RetainerBalance> {
+        get_retainer_balance(&env, &retainor, &retainee)
+    }
+
+    pub fn retainer_balance_unwrap(env: Env, retainor: Address, retainee: Address) -> RetainerBalance {
+        get_retainer_balance(&env, &retainor, &retainee).unwrap()
+    }
+
+    pub fn engagement_stats(env: Env, retainor: Address, retainee: Address) -> EngagementStats {
+        get_stats(&env, &StorageKey::EngagementStats(retainor, retainee))
+    }
+
+    pub fn retainor_stats(env: Env, retainor: Address, token: Address) -> EngagementStats {
+        get_stats(&env, &StorageKey::RetainorStats(retainor, token))
+    }
+
+    pub fn retainee_stats(env: Env, retainee: Address, token: Address) -> EngagementStats {
+        get_stats(&env, &StorageKey::RetaineeStats(retainee, token))
+    }
+
+    pub fn billing_cap_headroom(env: Env, retainor: Address, retainee: Address) -> Option<i128> {
+        get_cap_headroom(&env, &retainor, &retainee)
+    }
+
+    pub fn billing_cap(env: Env, retainor: Address, retainee: Address) -> Option<BillingCap> {
+        get_billing_cap(&env, &retainor, &retainee)
+    }
+
+    pub fn set_billing_cap(env: Env, retainor: Address, retainee: Address, amount: i128, period: u64) {
+        retainor.require_auth();
+        check_positive_amount(amount);
+        if period == 0 {
+            panic!("Invalid period");
+        }
+        clear_billing_cap(&env, &retainor, &retainee);
+        set_billing_cap(&env, &retainor, &retainee, BillingCap {
+            amount,
+            period,
+            start: env.ledger().timestamp(),
+        });
+    }
+
+    pub fn remove_billing_cap(env: Env, retainor: Address, retainee: Address) {
+        retainor.require_auth();
+        clear_billing_cap(&env, &retainor, &retainee);
+    }
+
+    pub fn set_budget(env: Env, retainor: Address, retainee: Address, amount: i128, thresholds: Vec<u32>, require_override: bool) {
+        retainor.require_auth();
+        check_positive_amount(amount);
+        // keep the amount already spent when revising an existing budget
+        let spent = get_budget(&env, &retainor, &retainee).map(|budget| budget.spent).unwrap_or(0);
+        set_budget(&env, &retainor, &retainee, Budget {
+            amount,
+            thresholds,
+            require_override,
+            spent,
+        });
+    }
+
+    pub fn remove_budget(env: Env, retainor: Address, retainee: Address) {
+        retainor.require_auth();
+        clear_budget(&env, &retainor, &retainee);
+    }
+
+    pub fn budget(env: Env, retainor: Address, retainee: Address) -> Option<Budget> {
+        get_budget(&env, &retainor, &retainee)
+    }
+
+    pub fn add_retainer_balance(env: Env, retainor: Address, retainee: Address, additional_amount: i128, token: Address) {
+        retainor.require_auth();
+        check_positive_amount(additional_amount);
+        if get_allowance_token(&env, &retainor, &retainee).is_some() {
+            panic!("Engagement is allowance funded");
+        }
+        let mut retainer_balance = get_retainer_balance(&env, &retainor, &retainee).unwrap_or(RetainerBalance {
+            amount: 0,
+            token: token.clone(),
+        });
+        if retainer_balance.token != token {
+            panic!("Token mismatch");
+        }
+        retainer_balance.amount = retainer_balance.amount.checked_add(additional_amount).unwrap();
+        // transfer tokens to contract
+        token::Client::new(&env, &retainer_balance.token).transfer(&retainor, &env.current_contract_address(), &additional_amount);
+        // update state
+        update_stats(&env, &retainor, &retainee, &retainer_balance.token, |stats| {
+            stats.amount_deposited = stats.amount_deposited.checked_add(additional_amount).unwrap();
+        });
+        record_contribution(&env, &retainor, &retainee, &retainor, additional_amount);
+        set_retainer_balance(&env, &retainor, &retainee, retainer_balance);
+    }
+
+    // Lets anyone, such as a parent company or insurer, fund an engagement on the retainor's behalf
+    pub fn sponsor_retainer(env: Env, funder: Address, retainor: Address, retainee: Address, amount: i128) {
+        funder.require_auth();
+        check_positive_amount(amount);
+        if get_allowance_token(&env, &retainor, &retainee).is_some() {
+            panic!("Engagement is allowance funded");
+        }
+        // only the retainor can open a retainer and choose its token
+        let mut retainer_balance = match get_retainer_balance(&env, &retainor, &retainee) {
+            Some(balance) => balance,
+            None => panic!("No retained balance"),
+        };
+        retainer_balance.amount = retainer_balance.amount.checked_add(amount).unwrap();
+        let token = retainer_balance.token.clone();
+        token::Client::new(&env, &token).transfer(&funder, &env.current_contract_address(), &amount);
+        update_stats(&env, &retainor, &retainee, &token, |stats| {
+            stats.amount_deposited = stats.amount_deposited.checked_add(amount).unwrap();
+        });
+        record_contribution(&env, &retainor, &retainee, &funder, amount);
+        set_retainer_balance(&env, &retainor, &retainee, retainer_balance);
+    }
+
+    pub fn contributions(env: Env, retainor: Address, retainee: Address) -> Vec<Contribution> {
+        get_contributions(&env, &retainor, &retainee)
+    }
+
+    pub fn set_refund_rule(env: Env, retainor: Address, retainee: Address, rule: RefundRule) {
+        retainor.require_auth();
+        env.storage()
+            .persistent()
+            .set::<_, RefundRule>(&StorageKey::RefundRule(retainor.clone(), retainee.clone()), &rule);
+    }
+
+    pub fn refund_rule(env: Env, retainor: Address, retainee: Address) -> RefundRule {
+        get_refund_rule(&env, &retainor, &retainee)
+    }
+
+    pub fn remove_retainer_balance(env: Env, retainor: Address, retainee: Address, amount: i128) {
+        retainor.require_auth();
+        check_positive_amount(amount);
+        if has_pending_payment(&env, &retainor, &retainee) {
+            panic!("Pending payment exists");
+        }
+        let retainer_balance = get_retainer_balance(&env, &retainor, &retainee).unwrap();
+        let committed = get_committed(&env, &retainor, &retainee);
+        if retainer_balance.amount.checked_sub(committed).unwrap() < amount {
+            panic!("Insufficient retained balance");
+        }
+        refund_to_funders(&env, &retainor, &retainee, amount);
+    }
+
+    // Moves retained funds between two of the retainor's engagements without a token transfer
+    pub fn reallocate(env: Env, retainor: Address, from_retainee: Address, to_retainee: Address, amount: i128) {
+        retainor.require_auth();
+        check_positive_amount(amount);
+        if from_retainee == to_retainee {
+            panic!("Cannot reallocate to the same engagement");
+        }
+        if get_allowance_token(&env, &retainor, &to_retainee).is_some() {
+            panic!("Engagement is allowance funded");
+        }
+        // a pool member bills against the pool, so a balance of its own would never be drawn on
+        if get_member_pool(&env, &retainor, &to_retainee).is_some() {
+            panic!("Member has its own retainer");
+        }
+        if is_expired(&env, &retainor, &to_retainee) {
+            panic!("Retainer expired");
+        }
+        if has_pending_payment(&env, &retainor, &from_retainee) {
+            panic!("Pending payment exists");
+        }
+        // sponsor money stays with the engagement it was given to
+        if has_sponsors(&env, &retainor, &from_retainee) {
+            panic!("Engagement has sponsors");
+        }
+        let mut from_balance = get_retainer_balance(&env, &retainor, &from_retainee).unwrap();
+        let committed = get_committed(&env, &retainor, &from_retainee);
+        if from_balance.amount.checked_sub(committed).unwrap() < amount {
+            panic!("Insufficient retained balance");
+        }
+        let token = from_balance.token.clone();
+        let mut to_balance = get_retainer_balance(&env, &retainor, &to_retainee).unwrap_or(RetainerBalance {
+            amount: 0,
+            token: token.clone(),
+        });
+        if to_balance.token != token {
+            panic!("Token mismatch");
         }
+        from_balance.amount = from_balance.amount.checked_sub(amount).unwrap();
+        to_balance.amount = to_balance.amount.checked_add(amount).unwrap();
+        update_stats(&env, &retainor, &from_retainee, &token, |stats| {
+            stats.amount_withdrawn = stats.amount_withdrawn.checked_add(amount).unwrap();
+        });
+        update_stats(&env, &retainor, &to_retainee, &token, |stats| {
+            stats.amount_deposited = stats.amount_deposited.checked_add(amount).unwrap();
+        });
+        split_refund(&env, &retainor, &from_retainee, amount);
+        record_contribution(&env, &retainor, &to_retainee, &retainor, amount);
+        set_retainer_balance(&env, &retainor, &from_retainee, from_balance);
+        set_retainer_balance(&env, &retainor, &to_retainee, to_balance);
     }
 
-    pub fn retainer_balance(env: Env, retainor: Address, retainee: Address) -> Option<RetainerBalance> {
-        get_retainer_balance(&env, &retainor, &retainee)
+    pub fn add_milestone(env: Env, retainor: Address, retainee: Address, description: String, amount: i128, due_date: u64) -> u32 {
+        retainor.require_auth();
+        retainee.require_auth();
+        check_positive_amount(amount);
+        if get_allowance_token(&env, &retainor, &retainee).is_none() {
+            let retained = match get_retainer_balance(&env, &retainor, &retainee) {
+                Some(balance) => balance.amount,
+                None => panic!("No retained balance"),
+            };
+            let committed = get_committed(&env, &retainor, &retainee).checked_add(amount).unwrap();
+            if retained < committed {
+                panic!("Insufficient retained balance");
+            }
+        }
+        push_milestone(&env, &retainor, &retainee, Milestone {
+            description,
+            amount,
+            due_date,
+            status: MilestoneStatus::Open,
+        })
     }
 
-    pub fn retainer_balance_unwrap(env: Env, retainor: Address, retainee: Address) -> RetainerBalance {
-        get_retainer_balance(&env, &retainor, &retainee).unwrap()
+    pub fn deliver_milestone(env: Env, retainor: Address, retainee: Address, index: u32) {
+        retainee.require_auth();
+        let mut milestone = get_milestone(&env, &retainor, &retainee, index);
+        if milestone.status != MilestoneStatus::Open {
+            panic!("Milestone not open");
+        }
+        milestone.status = MilestoneStatus::Delivered;
+        set_milestone(&env, &retainor, &retainee, index, milestone);
     }
 
-    pub fn add_retainer_balance(env: Env, retainor: Address, retainee: Address, additional_amount: i128, token: Address) {
+    // Sends a delivered milestone back to the retainee for more work
+    pub fn reject_milestone(env: Env, retainor: Address, retainee: Address, index: u32) {
         retainor.require_auth();
-        check_positive_amount(additional_amount);
-        let mut retainer_balance = get_retainer_balance(&env, &retainor, &retainee).unwrap_or(RetainerBalance {
-            amount: 0,
-            token: token.clone(),
+        let mut milestone = get_milestone(&env, &retainor, &retainee, index);
+        if milestone.status != MilestoneStatus::Delivered {
+            panic!("Milestone not delivered");
+        }
+        milestone.status = MilestoneStatus::Open;
+        set_milestone(&env, &retainor, &retainee, index, milestone);
+    }
+
+    pub fn accept_milestone(env: Env, retainor: Address, retainee: Address, index: u32, over_budget: bool, notes: String, date: String) {
+        retainor.require_auth();
+        let mut milestone = get_milestone(&env, &retainor, &retainee, index);
+        if milestone.status != MilestoneStatus::Delivered {
+            panic!("Milestone not delivered");
+        }
+        if !over_budget && exceeds_budget(&env, &retainor, &retainee, milestone.amount) {
+            panic!("Budget exceeded");
+        }
+        let token = match get_allowance_token(&env, &retainor, &retainee) {
+            Some(token) => token,
+            None => get_retainer_balance(&env, &retainor, &retainee).unwrap().token,
+        };
+        milestone.status = MilestoneStatus::Accepted;
+        set_milestone(&env, &retainor, &retainee, index, milestone.clone());
+        let (payouts, withheld) = pay_from_retainer(&env, &retainor, &retainee, &token, milestone.amount);
+        update_stats(&env, &retainor, &retainee, &token, |stats| {
+            stats.bills_approved += 1;
+            stats.amount_paid = stats.amount_paid.checked_add(milestone.amount).unwrap();
         });
-        if retainer_balance.token != token {
-            panic!("Token mismatch");
+        let receipt = Receipt {
+            bill: Bill {
+                amount: milestone.amount,
+                token,
+                notes: milestone.description,
+                date: date.clone(),
+            },
+            notes,
+            date,
+            status: ApprovalStatus::Milestone,
+            settled_amount: milestone.amount,
+            withheld,
+            related_receipt: None,
+            payouts,
+            member: None,
+        };
+        push_receipt(&env, &retainor, &retainee, receipt);
+    }
+
+    pub fn milestones(env: Env, retainor: Address, retainee: Address) -> Vec<Milestone> {
+        get_milestones(&env, &retainor, &retainee)
+    }
+
+    pub fn start_stream(env: Env, retainor: Address, retainee: Address, rate_per_second: i128, start: u64, end: u64) {
+        retainor.require_auth();
+        check_positive_amount(rate_per_second);
+        if end <= start {
+            panic!("Invalid stream period");
         }
-        retainer_balance.amount = retainer_balance.amount.checked_add(additional_amount).unwrap();
-        // transfer tokens to contract
-        token::Client::new(&env, &retainer_balance.token).transfer(&retainor, &env.current_contract_address(), &additional_amount);
-        // update state
-        set_retainer_balance(&env, &retainor, &retainee, retainer_balance);
+        if get_stream(&env, &retainor, &retainee).is_some() {
+            panic!("Stream already exists");
+        }
+        if get_allowance_token(&env, &retainor, &retainee).is_some() {
+            panic!("Engagement is allowance funded");
+        }
+        let retained = match get_retainer_balance(&env, &retainor, &retainee) {
+            Some(balance) => balance.amount,
+            None => panic!("No retained balance"),
+        };
+        let total = rate_per_second.checked_mul((end - start) as i128).unwrap();
+        if retained < get_committed(&env, &retainor, &retainee).checked_add(total).unwrap() {
+            panic!("Insufficient retained balance");
+        }
+        set_stream(&env, &retainor, &retainee, Stream {
+            rate_per_second,
+            start,
+            end,
+            claimed_until: start,
+        });
     }
 
-    pub fn remove_retainer_balance(env: Env, retainor: Address, retainee: Address, amount: i128) {
+    pub fn claim_stream(env: Env, retainor: Address, retainee: Address, date: String) -> i128 {
+        retainee.require_auth();
+        let stream = match get_stream(&env, &retainor, &retainee) {
+            Some(stream) => stream,
+            None => panic!("No stream"),
+        };
+        let end = stream.end;
+        let amount = claim_stream(&env, &retainor, &retainee, stream, date);
+        if amount == 0 {
+            panic!("Nothing to claim");
+        }
+        if env.ledger().timestamp() >= end {
+            clear_stream(&env, &retainor, &retainee);
+        }
+        amount
+    }
+
+    // Pays the retainee what has accrued so far and refunds the rest of the stream to the retainor
+    pub fn cancel_stream(env: Env, retainor: Address, retainee: Address, date: String) -> i128 {
+        retainor.require_auth();
+        let stream = match get_stream(&env, &retainor, &retainee) {
+            Some(stream) => stream,
+            None => panic!("No stream"),
+        };
+        claim_stream(&env, &retainor, &retainee, stream.clone(), date);
+        let now = env.ledger().timestamp().max(stream.start);
+        let refund = stream.rate_per_second.checked_mul(stream.end.saturating_sub(now) as i128).unwrap();
+        clear_stream(&env, &retainor, &retainee);
+        if refund > 0 {
+            refund_to_funders(&env, &retainor, &retainee, refund);
+        }
+        refund
+    }
+
+    pub fn set_payment_schedule(env: Env, retainor: Address, retainee: Address, amount: i128, interval: u64, count: u32, first_due: u64) {
         retainor.require_auth();
+        retainee.require_auth();
         check_positive_amount(amount);
+        // the last installment's due date must fit in a timestamp
+        let last_due = interval.checked_mul(count as u64).and_then(|span| first_due.checked_add(span));
+        if interval == 0 || count == 0 || last_due.is_none() {
+            panic!("Invalid schedule");
+        }
+        if get_allowance_token(&env, &retainor, &retainee).is_some() {
+            panic!("Engagement is allowance funded");
+        }
+        set_payment_schedule(&env, &retainor, &retainee, PaymentSchedule {
+            amount,
+            interval,
+            count,
+            next_due: first_due,
+            paid: 0,
+            skipped: 0,
+        });
+    }
+
+    pub fn cancel_payment_schedule(env: Env, retainor: Address, retainee: Address) {
+        retainor.require_auth();
+        clear_payment_schedule(&env, &retainor, &retainee);
+    }
+
+    pub fn payment_schedule(env: Env, retainor: Address, retainee: Address) -> Option<PaymentSchedule> {
+        get_payment_schedule(&env, &retainor, &retainee)
+    }
+
+    // Anyone may pay out the installments that have come due, skipping those the balance can't cover.
+    // At most MAX_INSTALLMENTS_PER_CALL are handled per call; call again to work off the rest.
+    pub fn process_due_payments(env: Env, retainor: Address, retainee: Address, keeper: Address) -> u32 {
+        let mut schedule = match get_payment_schedule(&env, &retainor, &retainee) {
+            Some(schedule) => schedule,
+            None => panic!("No payment schedule"),
+        };
+        let token = get_retainer_balance(&env, &retainor, &retainee).unwrap().token;
+        let now = env.ledger().timestamp();
+        let empty = String::from_str(&env, "");
+        let mut paid = 0;
+        let mut processed = 0;
+        while processed < MAX_INSTALLMENTS_PER_CALL && schedule.paid + schedule.skipped < schedule.count && schedule.next_due <= now {
+            processed += 1;
+            let mut receipt = Receipt {
+                bill: Bill {
+                    amount: schedule.amount,
+                    token: token.clone(),
+                    notes: empty.clone(),
+                    date: empty.clone(),
+                },
+                notes: empty.clone(),
+                date: empty.clone(),
+                status: ApprovalStatus::Skipped,
+                settled_amount: 0,
+                withheld: 0,
+                related_receipt: None,
+                payouts: Vec::new(&env),
+                member: None,
+            };
+            if can_pay_installment(&env, &retainor, &retainee, schedule.amount) {
+                let (payouts, withheld) = pay_from_retainer(&env, &retainor, &retainee, &token, schedule.amount);
+                update_stats(&env, &retainor, &retainee, &token, |stats| {
+                    stats.amount_paid = stats.amount_paid.checked_add(schedule.amount).unwrap();
+                });
+                receipt.status = ApprovalStatus::Scheduled;
+                receipt.settled_amount = schedule.amount;
+                receipt.withheld = withheld;
+                receipt.payouts = payouts;
+                schedule.paid += 1;
+                paid += 1;
+            } else {
+                env.events().publish(
+                    (Symbol::new(&env, "installment_skipped"), retainor.clone(), retainee.clone()),
+                    (schedule.next_due, schedule.amount),
+                );
+                schedule.skipped += 1;
+            }
+            push_receipt(&env, &retainor, &retainee, receipt);
+            schedule.next_due = schedule.next_due.checked_add(schedule.interval).unwrap();
+        }
+        // skipping installments moves no funds, so a keeper gains nothing from cranking while the balance is short
+        if paid > 0 {
+            pay_keeper_reward(&env, &retainor, &retainee, &keeper);
+        }
+        set_payment_schedule(&env, &retainor, &retainee, schedule);
+        paid
+    }
+
+    pub fn stream(env: Env, retainor: Address, retainee: Address) -> Option<Stream> {
+        get_stream(&env, &retainor, &retainee)
+    }
+
+    pub fn stream_claimable(env: Env, retainor: Address, retainee: Address) -> i128 {
+        match get_stream(&env, &retainor, &retainee) {
+            Some(stream) => stream_accrued(&stream, env.ledger().timestamp()),
+            None => 0,
+        }
+    }
+
+    pub fn set_allowance_funding(env: Env, retainor: Address, retainee: Address, token: Address) {
+        retainor.require_auth();
         if get_pending_payment(&env, &retainor, &retainee).is_some() {
             panic!("Pending payment exists");
         }
-        let mut retainer_balance = get_retainer_balance(&env, &retainor, &retainee).unwrap();
-        if retainer_balance.amount < amount {
-            panic!("Insufficient retained balance");
+        if let Some(balance) = get_retainer_balance(&env, &retainor, &retainee) {
+            if balance.amount > 0 {
+                panic!("Retained balance exists");
+            }
         }
-        retainer_balance.amount = retainer_balance.amount.checked_sub(amount).unwrap();
-        // transfer tokens to retainee
-        token::Client::new(&env, &retainer_balance.token).transfer(&env.current_contract_address(), &retainor, &amount);
-        // update state
-        set_retainer_balance(&env, &retainor, &retainee, retainer_balance);
+        set_allowance_token(&env, &retainor, &retainee, token);
+    }
+
+    pub fn remove_allowance_funding(env: Env, retainor: Address, retainee: Address) {
+        retainor.require_auth();
+        if get_pending_payment(&env, &retainor, &retainee).is_some() {
+            panic!("Pending payment exists");
+        }
+        clear_allowance_token(&env, &retainor, &retainee);
+    }
+
+    pub fn allowance_funding(env: Env, retainor: Address, retainee: Address) -> Option<Address> {
+        get_allowance_token(&env, &retainor, &retainee)
+    }
+
+    pub fn set_payout_split(env: Env, retainor: Address, retainee: Address, split: Vec<SplitShare>) {
+        retainee.require_auth();
+        if split.is_empty() {
+            panic!("Empty payout split");
+        }
+        let mut total: u32 = 0;
+        for share in split.iter() {
+            if share.bps == 0 {
+                panic!("Invalid payout share");
+            }
+            total = total.checked_add(share.bps).unwrap();
+        }
+        if total != 10_000 {
+            panic!("Payout split must total 10000 bps");
+        }
+        // a split redirects payouts just like a payout address, so it waits out the same delay
+        settle_payout_split(&env, &retainor, &retainee);
+        set_payout_split_change(&env, &retainor, &retainee, PayoutSplitChange {
+            split,
+            effective_at: env.ledger().timestamp().checked_add(PAYOUT_ADDRESS_DELAY).unwrap(),
+        });
+    }
+
+    pub fn remove_payout_split(env: Env, retainor: Address, retainee: Address) {
+        retainee.require_auth();
+        settle_payout_split(&env, &retainor, &retainee);
+        set_payout_split_change(&env, &retainor, &retainee, PayoutSplitChange {
+            split: Vec::new(&env),
+            effective_at: env.ledger().timestamp().checked_add(PAYOUT_ADDRESS_DELAY).unwrap(),
+        });
+    }
+
+    pub fn cancel_payout_split_change(env: Env, retainor: Address, retainee: Address) {
+        retainee.require_auth();
+        settle_payout_split(&env, &retainor, &retainee);
+        clear_payout_split_change(&env, &retainor, &retainee);
+    }
+
+    pub fn payout_split(env: Env, retainor: Address, retainee: Address) -> Option<Vec<SplitShare>> {
+        get_payout_split(&env, &retainor, &retainee)
+    }
+
+    pub fn payout_split_change(env: Env, retainor: Address, retainee: Address) -> Option<PayoutSplitChange> {
+        get_payout_split_change(&env, &retainor, &retainee)
+    }
+
+    pub fn set_payout_address(env: Env, retainor: Address, retainee: Address, address: Address) {
+        retainee.require_auth();
+        // settle a change that has already taken effect before scheduling the next one
+        let current = get_payout_address(&env, &retainor, &retainee);
+        set_payout_address(&env, &retainor, &retainee, current);
+        set_payout_address_change(&env, &retainor, &retainee, PayoutAddressChange {
+            address,
+            effective_at: env.ledger().timestamp().checked_add(PAYOUT_ADDRESS_DELAY).unwrap(),
+        });
+    }
+
+    pub fn cancel_payout_address_change(env: Env, retainor: Address, retainee: Address) {
+        retainee.require_auth();
+        let current = get_payout_address(&env, &retainor, &retainee);
+        set_payout_address(&env, &retainor, &retainee, current);
+        clear_payout_address_change(&env, &retainor, &retainee);
+    }
+
+    pub fn payout_address(env: Env, retainor: Address, retainee: Address) -> Address {
+        get_payout_address(&env, &retainor, &retainee)
+    }
+
+    pub fn payout_address_change(env: Env, retainor: Address, retainee: Address) -> Option<PayoutAddressChange> {
+        get_payout_address_change(&env, &retainor, &retainee)
+    }
+
+    pub fn set_withholding(env: Env, retainor: Address, retainee: Address, rate_bps: u32, destination: Address) {
+        retainor.require_auth();
+        if rate_bps == 0 || rate_bps > 10_000 {
+            panic!("Invalid withholding rate");
+        }
+        set_withholding(&env, &retainor, &retainee, Withholding {
+            rate_bps,
+            destination,
+        });
+    }
+
+    pub fn remove_withholding(env: Env, retainor: Address, retainee: Address) {
+        retainor.require_auth();
+        clear_withholding(&env, &retainor, &retainee);
+    }
+
+    pub fn withholding(env: Env, retainor: Address, retainee: Address) -> Option<Withholding> {
+        get_withholding(&env, &retainor, &retainee)
+    }
+
+    pub fn withheld_in_year(env: Env, retainor: Address, retainee: Address, year: u32) -> i128 {
+        get_withheld_in_year(&env, &retainor, &retainee, year)
+    }
+
+    pub fn set_evergreen_target(env: Env, retainor: Address, retainee: Address, target: i128) {
+        retainor.require_auth();
+        check_positive_amount(target);
+        set_evergreen_target(&env, &retainor, &retainee, target);
+    }
+
+    pub fn remove_evergreen_target(env: Env, retainor: Address, retainee: Address) {
+        retainor.require_auth();
+        clear_evergreen_target(&env, &retainor, &retainee);
+    }
+
+    pub fn evergreen_target(env: Env, retainor: Address, retainee: Address) -> Option<i128> {
+        get_evergreen_target(&env, &retainor, &retainee)
+    }
+
+    // Anyone may top up an evergreen retainer from the retainor's allowance
+    pub fn replenish(env: Env, retainor: Address, retainee: Address, keeper: Address) -> i128 {
+        let amount = replenish(&env, &retainor, &retainee);
+        if amount > 0 {
+            pay_keeper_reward(&env, &retainor, &retainee, &keeper);
+        }
+        amount
+    }
+
+    pub fn set_expiry(env: Env, retainor: Address, retainee: Address, expires_at: u64) {
+        retainor.require_auth();
+        retainee.require_auth();
+        if expires_at <= env.ledger().timestamp() {
+            panic!("Expiry must be in the future");
+        }
+        set_expiry(&env, &retainor, &retainee, expires_at);
+    }
+
+    pub fn expiry(env: Env, retainor: Address, retainee: Address) -> Option<u64> {
+        get_expiry(&env, &retainor, &retainee)
+    }
+
+    // Anyone may return the unused balance of an expired retainer to the retainor. Bills still
+    // pending, including those of pool members, expire unpaid.
+    pub fn sweep_expired(env: Env, retainor: Address, retainee: Address, keeper: Address) -> i128 {
+        if !is_expired(&env, &retainor, &retainee) {
+            panic!("Retainer not expired");
+        }
+        expire_pending_payment(&env, &retainor, &retainee);
+        for member in get_pool_members(&env, &retainor, &retainee).iter() {
+            expire_pending_payment(&env, &retainor, &member.member);
+        }
+        let committed = get_committed(&env, &retainor, &retainee);
+        let amount = match get_retainer_balance(&env, &retainor, &retainee) {
+            // milestones and streams already agreed stay in escrow
+            Some(balance) => balance.amount.checked_sub(committed).unwrap(),
+            None => panic!("No retained balance"),
+        };
+        if amount > 0 {
+            refund_to_funders(&env, &retainor, &retainee, amount);
+            pay_keeper_reward(&env, &retainor, &retainee, &keeper);
+        }
+        // the engagement is over, so the bond has nothing left to secure
+        if committed == 0 {
+            return_bond(&env, &retainor, &retainee);
+        }
+        clear_evergreen_target(&env, &retainor, &retainee);
+        amount
+    }
+
+    pub fn fund_keeper_reward(env: Env, retainor: Address, retainee: Address, amount: i128, reward: i128) {
+        retainor.require_auth();
+        check_positive_amount(amount);
+        check_positive_amount(reward);
+        let mut fund = match get_keeper_fund(&env, &retainor, &retainee) {
+            Some(fund) => fund,
+            None => {
+                let token = match get_allowance_token(&env, &retainor, &retainee) {
+                    Some(token) => token,
+                    None => match get_retainer_balance(&env, &retainor, &retainee) {
+                        Some(balance) => balance.token,
+                        None => panic!("No retained balance"),
+                    },
+                };
+                KeeperFund {
+                    token,
+                    balance: 0,
+                    reward,
+                }
+            }
+        };
+        fund.balance = fund.balance.checked_add(amount).unwrap();
+        fund.reward = reward;
+        token::Client::new(&env, &fund.token).transfer(&retainor, &env.current_contract_address(), &amount);
+        set_keeper_fund(&env, &retainor, &retainee, fund);
+    }
+
+    pub fn withdraw_keeper_fund(env: Env, retainor: Address, retainee: Address) -> i128 {
+        retainor.require_auth();
+        let mut fund = match get_keeper_fund(&env, &retainor, &retainee) {
+            Some(fund) => fund,
+            None => panic!("No keeper fund"),
+        };
+        let amount = fund.balance;
+        if amount > 0 {
+            token::Client::new(&env, &fund.token).transfer(&env.current_contract_address(), &retainor, &amount);
+        }
+        fund.balance = 0;
+        set_keeper_fund(&env, &retainor, &retainee, fund);
+        amount
+    }
+
+    pub fn keeper_fund(env: Env, retainor: Address, retainee: Address) -> Option<KeeperFund> {
+        get_keeper_fund(&env, &retainor, &retainee)
+    }
+
+    pub fn require_bond(env: Env, retainor: Address, retainee: Address, amount: i128, arbiter: Address) {
+        retainor.require_auth();
+        retainee.require_auth();
+        check_positive_amount(amount);
+        let posted = get_bond(&env, &retainor, &retainee).map(|bond| bond.posted).unwrap_or(0);
+        let token = match get_allowance_token(&env, &retainor, &retainee) {
+            Some(token) => token,
+            None => match get_retainer_balance(&env, &retainor, &retainee) {
+                Some(balance) => balance.token,
+                None => panic!("No retained balance"),
+            },
+        };
+        set_bond(&env, &retainor, &retainee, Bond {
+            token,
+            required: amount,
+            posted,
+            arbiter,
+        });
+    }
+
+    pub fn post_bond(env: Env, retainor: Address, retainee: Address, amount: i128) {
+        retainee.require_auth();
+        check_positive_amount(amount);
+        let mut bond = match get_bond(&env, &retainor, &retainee) {
+            Some(bond) => bond,
+            None => panic!("No bond required"),
+        };
+        bond.posted = bond.posted.checked_add(amount).unwrap();
+        token::Client::new(&env, &bond.token).transfer(&retainee, &env.current_contract_address(), &amount);
+        set_bond(&env, &retainor, &retainee, bond);
+    }
+
+    // The arbiter rules against the retainee and moves part of the bond to the retainor
+    pub fn slash_bond(env: Env, retainor: Address, retainee: Address, amount: i128, notes: String, date: String) {
+        check_positive_amount(amount);
+        let mut bond = match get_bond(&env, &retainor, &retainee) {
+            Some(bond) => bond,
+            None => panic!("No bond required"),
+        };
+        bond.arbiter.require_auth();
+        if bond.posted < amount {
+            panic!("Insufficient bond");
+        }
+        bond.posted = bond.posted.checked_sub(amount).unwrap();
+        token::Client::new(&env, &bond.token).transfer(&env.current_contract_address(), &retainor, &amount);
+        let receipt = Receipt {
+            bill: Bill {
+                amount,
+                token: bond.token.clone(),
+                notes: notes.clone(),
+                date: date.clone(),
+            },
+            notes,
+            date,
+            status: ApprovalStatus::Slashed,
+            settled_amount: 0,
+            withheld: 0,
+            related_receipt: None,
+            payouts: Vec::new(&env),
+            member: None,
+        };
+        push_receipt(&env, &retainor, &retainee, receipt);
+        set_bond(&env, &retainor, &retainee, bond);
+    }
+
+    // Lets `member` bill against the retainer held for `pool`, up to an optional sub-limit
+    pub fn set_pool_member(env: Env, retainor: Address, pool: Address, member: Address, limit: Option<i128>) {
+        retainor.require_auth();
+        if let Some(limit) = limit {
+            check_positive_amount(limit);
+        }
+        if let Some(current) = get_member_pool(&env, &retainor, &member) {
+            if current != pool {
+                panic!("Member belongs to another pool");
+            }
+        }
+        if get_retainer_balance(&env, &retainor, &member).is_some() || get_allowance_token(&env, &retainor, &member).is_some() {
+            panic!("Member has its own retainer");
+        }
+        let mut members = get_pool_members(&env, &retainor, &pool);
+        let entry = PoolMember {
+            member: member.clone(),
+            limit,
+            spent: 0,
+        };
+        match members.iter().position(|m| m.member == member) {
+            Some(i) => {
+                let existing = members.get(i as u32).unwrap();
+                members.set(i as u32, PoolMember { spent: existing.spent, ..entry });
+            }
+            None => members.push_back(entry),
+        }
+        set_pool_members(&env, &retainor, &pool, members);
+        env.storage()
+            .persistent()
+            .set::<_, Address>(&StorageKey::PoolMembership(retainor.clone(), member.clone()), &pool);
+    }
+
+    pub fn remove_pool_member(env: Env, retainor: Address, pool: Address, member: Address) {
+        retainor.require_auth();
+        if get_member_pool(&env, &retainor, &member) != Some(pool.clone()) {
+            panic!("Not a pool member");
+        }
+        if get_pending_payment(&env, &retainor, &member).is_some() {
+            panic!("Pending payment exists");
+        }
+        let mut members = get_pool_members(&env, &retainor, &pool);
+        if let Some(i) = members.iter().position(|m| m.member == member) {
+            members.remove(i as u32);
+        }
+        set_pool_members(&env, &retainor, &pool, members);
+        env.storage()
+            .persistent()
+            .remove(&StorageKey::PoolMembership(retainor.clone(), member.clone()));
+    }
+
+    pub fn pool_members(env: Env, retainor: Address, pool: Address) -> Vec<PoolMember> {
+        get_pool_members(&env, &retainor, &pool)
+    }
+
+    pub fn member_pool(env: Env, retainor: Address, member: Address) -> Option<Address> {
+        get_member_pool(&env, &retainor, &member)
+    }
+
+    pub fn bond(env: Env, retainor: Address, retainee: Address) -> Option<Bond> {
+        get_bond(&env, &retainor, &retainee)
+    }
+
+    // Ends an engagement with nothing outstanding, refunding the retainer and returning the bond
+    pub fn close_engagement(env: Env, retainor: Address, retainee: Address) {
+        retainor.require_auth();
+        if has_pending_payment(&env, &retainor, &retainee) {
+            panic!("Pending payment exists");
+        }
+        if get_committed(&env, &retainor, &retainee) > 0 {
+            panic!("Committed funds outstanding");
+        }
+        if let Some(balance) = get_retainer_balance(&env, &retainor, &retainee) {
+            if balance.amount > 0 {
+                refund_to_funders(&env, &retainor, &retainee, balance.amount);
+            }
+        }
+        return_bond(&env, &retainor, &retainee);
+        clear_payment_schedule(&env, &retainor, &retainee);
+        clear_evergreen_target(&env, &retainor, &retainee);
+    }
+
+    // Hands the engagement over to a new retainor or retainee, keeping its history chain intact
+    pub fn assign_engagement(env: Env, retainor: Address, retainee: Address, new_retainor: Address, new_retainee: Address, notes: String, date: String) {
+        if (new_retainor == retainor) == (new_retainee == retainee) {
+            panic!("Exactly one party must change");
+        }
+        retainor.require_auth();
+        retainee.require_auth();
+        if new_retainor != retainor {
+            new_retainor.require_auth();
+        } else {
+            new_retainee.require_auth();
+        }
+        if get_pending_payment(&env, &retainor, &retainee).is_some() {
+            panic!("Pending payment exists");
+        }
+        // pool membership is keyed on the retainor and spans several engagements
+        if get_member_pool(&env, &retainor, &retainee).is_some() || !get_pool_members(&env, &retainor, &retainee).is_empty() {
+            panic!("Pool engagements can't be assigned");
+        }
+        if engagement_exists(&env, &new_retainor, &new_retainee) {
+            panic!("Engagement already exists");
+        }
+        let token = match get_allowance_token(&env, &retainor, &retainee) {
+            Some(token) => token,
+            None => match get_retainer_balance(&env, &retainor, &retainee) {
+                Some(balance) => balance.token,
+                None => panic!("No retained balance"),
+            },
+        };
+        move_engagement(&env, &retainor, &retainee, &new_retainor, &new_retainee);
+        if new_retainor != retainor {
+            // the incoming retainor takes over the outgoing retainor's own deposits
+            let mut contributions = get_contributions(&env, &new_retainor, &new_retainee);
+            for (i, mut contribution) in contributions.iter().enumerate() {
+                if contribution.funder == retainor {
+                    contribution.funder = new_retainor.clone();
+                    contributions.set(i as u32, contribution);
+                }
+            }
+            set_contributions(&env, &new_retainor, &new_retainee, contributions);
+            move_retainor_directory(&env, &retainor, &retainee, &new_retainor);
+            if let Some(mut info) = get_retainee_info(&env, &retainee) {
+                replace_in_directory(&mut info.retainors, &retainor, &new_retainor);
+                set_retainee_info(&env, &retainee, info);
+            }
+        } else {
+            move_retainee_directory(&env, &retainor, &retainee, &new_retainee);
+            if let Some(mut info) = get_retainor_info(&env, &retainor) {
+                replace_in_directory(&mut info.retainees, &retainee, &new_retainee);
+                set_retainor_info(&env, &retainor, info);
+            }
+        }
+        let receipt = Receipt {
+            bill: Bill {
+                amount: 0,
+                token,
+                notes: notes.clone(),
+                date: date.clone(),
+            },
+            notes,
+            date,
+            status: ApprovalStatus::Assigned,
+            settled_amount: 0,
+            withheld: 0,
+            related_receipt: None,
+            payouts: Vec::new(&env),
+            member: None,
+        };
+        push_receipt(&env, &new_retainor, &new_retainee, receipt);
     }
 
     pub fn retainee_info(env: Env, retainee: Address) -> RetaineeInfo {
@@ -311,7 +3155,7 @@ impl Contract {
         retainee.require_auth();
         let retainee_info = RetaineeInfo {
             name,
-            retainors: retainors,
+            retainors,
         };
         set_retainee_info(&env, &retainee, retainee_info);
     }
@@ -324,7 +3168,7 @@ impl Contract {
         retainor.require_auth();
         let retainor_info = RetainorInfo {
             name,
-            retainees: retainees,
+            retainees,
         };
         set_retainor_info(&env, &retainor, retainor_info);
     }
@@ -2,8 +2,8 @@
 
 use super::*;
 use soroban_sdk::{
-    testutils::Address as _, // AuthorizedFunction, AuthorizedInvocation},
-    token, Address, Env, vec
+    testutils::{Address as _, Events, Ledger}, // AuthorizedFunction, AuthorizedInvocation},
+    token, Address, Env, IntoVal, Symbol, symbol_short, vec
 };
 
 use token::Client as TokenClient;
@@ -277,8 +277,29 @@ fn test_resubmit_bill() {
         })
     );
 
-    contract.unsubmit_bill(&retainor, &retainee);
+    contract.unsubmit_bill(&retainor, &retainee, &str(&env, "Wrong amount"), &str(&env, "2021-01-01T00:00:00Z"));
     assert_eq!(contract.view_bill(&retainor, &retainee), None);
+    assert_eq!(contract.history_index(&retainor, &retainee), 1);
+    assert_eq!(
+        contract.view_receipt(&retainor, &retainee, &1),
+        Some(Receipt {
+            bill: Bill {
+                amount: 50,
+                notes: str(&env, "Bill 1"),
+                date: str(&env, "2021-01-01T00:00:00Z"),
+                token: token.address.clone(),
+            },
+            notes: str(&env, "Wrong amount"),
+            date: str(&env, "2021-01-01T00:00:00Z"),
+            status: ApprovalStatus::Withdrawn,
+            settled_amount: 0,
+            withheld: 0,
+            related_receipt: None,
+            payouts: vec![&env],
+            member: None,
+        })
+    );
+    assert_eq!(contract.engagement_stats(&retainor, &retainee).bills_withdrawn, 1);
 
     contract.submit_bill(&retainor, 
                             &retainee, 
@@ -314,6 +335,8 @@ fn test_resolve_bill_without_pending_payment() {
     contract.resolve_bill(&retainor, 
                             &retainee, 
                             &ApprovalStatus::Approved,
+                            &None,
+                            &false,
                             &str(&env, "Bill 1 resolved"),
                             &str(&env, "2021-01-01T00:00:00Z"));
     
@@ -345,6 +368,8 @@ fn test_resolve_bill_approved() {
     contract.resolve_bill(&retainor, 
                             &retainee, 
                             &ApprovalStatus::Approved,
+                            &None,
+                            &false,
                             &str(&env, "Bill 1 resolved"),
                             &str(&env, "2021-01-01T00:00:00Z"));
     
@@ -375,6 +400,11 @@ fn test_resolve_bill_approved() {
             notes: str(&env, "Bill 1 resolved"),
             date: str(&env, "2021-01-01T00:00:00Z"),
             status: ApprovalStatus::Approved,
+            settled_amount: 49,
+            withheld: 0,
+            related_receipt: None,
+            payouts: vec![&env],
+            member: None,
         }]
     );
     assert_eq!(contract.history_index(&retainor, &retainee), 1);
@@ -390,6 +420,11 @@ fn test_resolve_bill_approved() {
             notes: str(&env, "Bill 1 resolved"),
             date: str(&env, "2021-01-01T00:00:00Z"),
             status: ApprovalStatus::Approved,
+            settled_amount: 49,
+            withheld: 0,
+            related_receipt: None,
+            payouts: vec![&env],
+            member: None,
         })
     );
 }
@@ -421,6 +456,8 @@ fn test_resolve_bill_denied() {
     contract.resolve_bill(&retainor, 
                             &retainee, 
                             &ApprovalStatus::Denied,
+                            &Some(ReasonCode::OutOfScope),
+                            &false,
                             &str(&env, "Bill 1 resolved"),
                             &str(&env, "2021-01-01T00:00:00Z"));
     
@@ -451,9 +488,18 @@ fn test_resolve_bill_denied() {
             notes: str(&env, "Bill 1 resolved"),
             date: str(&env, "2021-01-01T00:00:00Z"),
             status: ApprovalStatus::Denied,
+            settled_amount: 0,
+            withheld: 0,
+            related_receipt: None,
+            payouts: vec![&env],
+            member: None,
         }]
     );
     assert_eq!(contract.history_index(&retainor, &retainee), 1);
+    assert_eq!(contract.receipt_reason(&retainor, &retainee, &1), Some(ReasonCode::OutOfScope));
+    let history = contract.view_history_with_reasons(&retainor, &retainee, &1, &1);
+    assert_eq!(history.len(), 1);
+    assert_eq!(history.get(0).unwrap().1, Some(ReasonCode::OutOfScope));
     assert_eq!(
         contract.view_receipt(&retainor, &retainee, &1),
         Some(Receipt {
@@ -466,6 +512,11 @@ fn test_resolve_bill_denied() {
             notes: str(&env, "Bill 1 resolved"),
             date: str(&env, "2021-01-01T00:00:00Z"),
             status: ApprovalStatus::Denied,
+            settled_amount: 0,
+            withheld: 0,
+            related_receipt: None,
+            payouts: vec![&env],
+            member: None,
         })
     );
 }
@@ -486,6 +537,8 @@ fn test_multiple_bills() {
     contract.resolve_bill(&retainor, 
                             &retainee, 
                             &ApprovalStatus::Approved,
+                            &None,
+                            &false,
                             &str(&env, "Bill 1 resolved"),
                             &str(&env, "2021-01-01T00:00:00Z"));
     
@@ -498,6 +551,8 @@ fn test_multiple_bills() {
     contract.resolve_bill(&retainor, 
                             &retainee, 
                             &ApprovalStatus::Approved,
+                            &None,
+                            &false,
                             &str(&env, "Bill 2 resolved"),
                             &str(&env, "2021-01-01T00:00:00Z"));
     
@@ -532,6 +587,11 @@ fn test_multiple_bills() {
                 notes: str(&env, "Bill 1 resolved"),
                 date: str(&env, "2021-01-01T00:00:00Z"),
                 status: ApprovalStatus::Approved,
+                settled_amount: 50,
+                withheld: 0,
+                related_receipt: None,
+                payouts: vec![&env],
+                member: None,
             }, 
             Receipt {
                 bill: Bill {
@@ -543,6 +603,11 @@ fn test_multiple_bills() {
                 notes: str(&env, "Bill 2 resolved"),
                 date: str(&env, "2021-01-01T00:00:00Z"),
                 status: ApprovalStatus::Approved,
+                settled_amount: 25,
+                withheld: 0,
+                related_receipt: None,
+                payouts: vec![&env],
+                member: None,
             }]
     );
     assert_eq!(contract.history_index(&retainor, &retainee), 2);
@@ -558,6 +623,11 @@ fn test_multiple_bills() {
             notes: str(&env, "Bill 2 resolved"),
             date: str(&env, "2021-01-01T00:00:00Z"),
             status: ApprovalStatus::Approved,
+            settled_amount: 25,
+            withheld: 0,
+            related_receipt: None,
+            payouts: vec![&env],
+            member: None,
         })
     );
 }
@@ -586,6 +656,8 @@ fn test_multiple_retainees() {
     contract.resolve_bill(&retainor, 
                             &retainee, 
                             &ApprovalStatus::Approved,
+                            &None,
+                            &false,
                             &str(&env, "R1 Bill 1 resolved"),
                             &str(&env, "2021-01-01T00:00:00Z"));
     
@@ -598,6 +670,8 @@ fn test_multiple_retainees() {
     contract.resolve_bill(&retainor, 
                             &retainee2, 
                             &ApprovalStatus::Approved,
+                            &None,
+                            &false,
                             &str(&env, "R2 Bill 1 resolved"),
                             &str(&env, "2021-01-01T00:00:00Z"));
     
@@ -650,6 +724,11 @@ fn test_multiple_retainees() {
             notes: str(&env, "R1 Bill 1 resolved"),
             date: str(&env, "2021-01-01T00:00:00Z"),
             status: ApprovalStatus::Approved,
+            settled_amount: 50,
+            withheld: 0,
+            related_receipt: None,
+            payouts: vec![&env],
+            member: None,
         })
     );
     assert_eq!(
@@ -664,6 +743,11 @@ fn test_multiple_retainees() {
             notes: str(&env, "R2 Bill 1 resolved"),
             date: str(&env, "2021-01-01T00:00:00Z"),
             status: ApprovalStatus::Approved,
+            settled_amount: 25,
+            withheld: 0,
+            related_receipt: None,
+            payouts: vec![&env],
+            member: None,
         })
     );
 }
@@ -698,6 +782,8 @@ fn test_multiple_retainors() {
     contract.resolve_bill(&retainor, 
                             &retainee, 
                             &ApprovalStatus::Approved,
+                            &None,
+                            &false,
                             &str(&env, "R1 Bill 1 resolved"),
                             &str(&env, "2021-01-01T00:00:00Z"));
     
@@ -710,6 +796,8 @@ fn test_multiple_retainors() {
     contract.resolve_bill(&retainor2, 
                             &retainee, 
                             &ApprovalStatus::Approved,
+                            &None,
+                            &false,
                             &str(&env, "R2 Bill 1 resolved"),
                             &str(&env, "2021-01-01T00:00:00Z"));
     
@@ -762,6 +850,11 @@ fn test_multiple_retainors() {
             notes: str(&env, "R1 Bill 1 resolved"),
             date: str(&env, "2021-01-01T00:00:00Z"),
             status: ApprovalStatus::Approved,
+            settled_amount: 50,
+            withheld: 0,
+            related_receipt: None,
+            payouts: vec![&env],
+            member: None,
         })
     );
     assert_eq!(
@@ -776,6 +869,1608 @@ fn test_multiple_retainors() {
             notes: str(&env, "R2 Bill 1 resolved"),
             date: str(&env, "2021-01-01T00:00:00Z"),
             status: ApprovalStatus::Approved,
+            settled_amount: 25,
+            withheld: 0,
+            related_receipt: None,
+            payouts: vec![&env],
+            member: None,
+        })
+    );
+}
+
+#[test]
+fn test_engagement_stats() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+    let retainee2 = Address::generate(&env);
+
+    contract.add_retainer_balance(&retainor, &retainee, &100, &token.address);
+    contract.add_retainer_balance(&retainor, &retainee2, &200, &token.address);
+    contract.remove_retainer_balance(&retainor, &retainee, &10);
+
+    contract.submit_bill(&retainor, 
+                            &retainee, 
+                            &50, 
+                            &str(&env, "Bill 1"), 
+                            &str(&env, "2021-01-01T00:00:00Z"));
+    contract.resolve_bill(&retainor, 
+                            &retainee, 
+                            &ApprovalStatus::Approved,
+                            &None,
+                            &false,
+                            &str(&env, "Bill 1 resolved"),
+                            &str(&env, "2021-01-01T00:00:00Z"));
+    contract.submit_bill(&retainor, 
+                            &retainee, 
+                            &25, 
+                            &str(&env, "Bill 2"), 
+                            &str(&env, "2021-01-01T00:00:00Z"));
+    contract.resolve_bill(&retainor, 
+                            &retainee, 
+                            &ApprovalStatus::Denied,
+                            &Some(ReasonCode::OutOfScope),
+                            &false,
+                            &str(&env, "Bill 2 resolved"),
+                            &str(&env, "2021-01-01T00:00:00Z"));
+    contract.submit_bill(&retainor, 
+                            &retainee2, 
+                            &30, 
+                            &str(&env, "R2 Bill 1"), 
+                            &str(&env, "2021-01-01T00:00:00Z"));
+    contract.resolve_bill(&retainor, 
+                            &retainee2, 
+                            &ApprovalStatus::Approved,
+                            &None,
+                            &false,
+                            &str(&env, "R2 Bill 1 resolved"),
+                            &str(&env, "2021-01-01T00:00:00Z"));
+
+    assert_eq!(
+        contract.engagement_stats(&retainor, &retainee),
+        EngagementStats {
+            bills_submitted: 2,
+            bills_approved: 1,
+            bills_denied: 1,
+            bills_countered: 0,
+            bills_withdrawn: 0,
+            amount_paid: 50,
+            amount_deposited: 100,
+            amount_withdrawn: 10,
+            amount_credited: 0,
+            reasons: ReasonCounts {
+                out_of_scope: 1,
+                ..Default::default()
+            },
+        }
+    );
+    assert_eq!(
+        contract.retainor_stats(&retainor, &token.address),
+        EngagementStats {
+            bills_submitted: 3,
+            bills_approved: 2,
+            bills_denied: 1,
+            bills_countered: 0,
+            bills_withdrawn: 0,
+            amount_paid: 80,
+            amount_deposited: 300,
+            amount_withdrawn: 10,
+            amount_credited: 0,
+            reasons: ReasonCounts {
+                out_of_scope: 1,
+                ..Default::default()
+            },
+        }
+    );
+    assert_eq!(
+        contract.retainee_stats(&retainee2, &token.address),
+        EngagementStats {
+            bills_submitted: 1,
+            bills_approved: 1,
+            bills_denied: 0,
+            bills_countered: 0,
+            bills_withdrawn: 0,
+            amount_paid: 30,
+            amount_deposited: 200,
+            amount_withdrawn: 0,
+            amount_credited: 0,
+            reasons: ReasonCounts::default(),
+        }
+    );
+    assert_eq!(
+        contract.retainee_stats(&retainee, &Address::generate(&env)),
+        EngagementStats::default()
+    );
+}
+
+
+#[test]
+fn test_issue_credit() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+
+    contract.add_retainer_balance(&retainor, &retainee, &100, &token.address);
+    contract.submit_bill(&retainor, 
+                            &retainee, 
+                            &60, 
+                            &str(&env, "Bill 1"), 
+                            &str(&env, "2021-01-01T00:00:00Z"));
+    contract.resolve_bill(&retainor, 
+                            &retainee, 
+                            &ApprovalStatus::Approved,
+                            &None,
+                            &false,
+                            &str(&env, "Bill 1 resolved"),
+                            &str(&env, "2021-01-01T00:00:00Z"));
+
+    contract.issue_credit(&retainor, 
+                            &retainee, 
+                            &15, 
+                            &1, 
+                            &str(&env, "Overbilled"), 
+                            &str(&env, "2021-01-02T00:00:00Z"));
+
+    // verify balances
+    assert_eq!(token.balance(&retainee), 45);
+    assert_eq!(token.balance(&contract.address), 55);
+    assert_eq!(
+        contract.retainer_balance(&retainor, &retainee),
+        Some(RetainerBalance {
+            amount: 55,
+            token: token.address.clone(),
+        })
+    );
+    assert_eq!(contract.credited_amount(&retainor, &retainee, &1), 15);
+    assert_eq!(contract.engagement_stats(&retainor, &retainee).amount_credited, 15);
+    assert_eq!(contract.history_index(&retainor, &retainee), 2);
+    assert_eq!(
+        contract.view_receipt(&retainor, &retainee, &2),
+        Some(Receipt {
+            bill: Bill {
+                amount: 15,
+                notes: str(&env, "Overbilled"),
+                date: str(&env, "2021-01-02T00:00:00Z"),
+                token: token.address.clone(),
+            },
+            notes: str(&env, "Overbilled"),
+            date: str(&env, "2021-01-02T00:00:00Z"),
+            status: ApprovalStatus::Credited,
+            settled_amount: 0,
+            withheld: 0,
+            related_receipt: Some(1),
+            payouts: vec![&env],
+            member: None,
+        })
+    );
+}
+
+#[test]
+#[should_panic(expected = "Credit exceeds billed amount")]
+fn test_issue_credit_exceeds_bill() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+
+    contract.add_retainer_balance(&retainor, &retainee, &100, &token.address);
+    contract.submit_bill(&retainor, 
+                            &retainee, 
+                            &60, 
+                            &str(&env, "Bill 1"), 
+                            &str(&env, "2021-01-01T00:00:00Z"));
+    contract.resolve_bill(&retainor, 
+                            &retainee, 
+                            &ApprovalStatus::Approved,
+                            &None,
+                            &false,
+                            &str(&env, "Bill 1 resolved"),
+                            &str(&env, "2021-01-01T00:00:00Z"));
+
+    contract.issue_credit(&retainor, &retainee, &40, &1, &str(&env, "Credit 1"), &str(&env, "2021-01-02T00:00:00Z"));
+    contract.issue_credit(&retainor, &retainee, &21, &1, &str(&env, "Credit 2"), &str(&env, "2021-01-02T00:00:00Z"));
+}
+
+#[test]
+fn test_counter_bill_accepted() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+
+    contract.add_retainer_balance(&retainor, &retainee, &100, &token.address);
+    contract.submit_bill(&retainor, 
+                            &retainee, 
+                            &80, 
+                            &str(&env, "Bill 1"), 
+                            &str(&env, "2021-01-01T00:00:00Z"));
+    contract.counter_bill(&retainor, 
+                            &retainee, 
+                            &60, 
+                            &ReasonCode::RateDispute,
+                            &str(&env, "Too many hours"), 
+                            &str(&env, "2021-01-02T00:00:00Z"));
+    assert_eq!(
+        contract.view_counter(&retainor, &retainee),
+        Some(CounterOffer {
+            amount: 60,
+            reason: ReasonCode::RateDispute,
+            notes: str(&env, "Too many hours"),
+            date: str(&env, "2021-01-02T00:00:00Z"),
+        })
+    );
+
+    contract.accept_counter(&retainor, &retainee, &str(&env, "2021-01-03T00:00:00Z"));
+
+    // verify balances
+    assert_eq!(token.balance(&retainee), 60);
+    assert_eq!(token.balance(&contract.address), 40);
+    // check final state
+    assert_eq!(contract.view_bill(&retainor, &retainee), None);
+    assert_eq!(contract.view_counter(&retainor, &retainee), None);
+    assert_eq!(
+        contract.view_receipt(&retainor, &retainee, &1),
+        Some(Receipt {
+            bill: Bill {
+                amount: 80,
+                notes: str(&env, "Bill 1"),
+                date: str(&env, "2021-01-01T00:00:00Z"),
+                token: token.address.clone(),
+            },
+            notes: str(&env, "Too many hours"),
+            date: str(&env, "2021-01-03T00:00:00Z"),
+            status: ApprovalStatus::Countered,
+            settled_amount: 60,
+            withheld: 0,
+            related_receipt: None,
+            payouts: vec![&env],
+            member: None,
+        })
+    );
+    assert_eq!(contract.engagement_stats(&retainor, &retainee).bills_countered, 1);
+    assert_eq!(contract.engagement_stats(&retainor, &retainee).amount_paid, 60);
+    assert_eq!(contract.engagement_stats(&retainor, &retainee).reasons.rate_dispute, 1);
+    assert_eq!(contract.receipt_reason(&retainor, &retainee, &1), Some(ReasonCode::RateDispute));
+}
+
+#[test]
+#[should_panic(expected = "No counter offer")]
+fn test_accept_counter_after_resubmit() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+
+    contract.add_retainer_balance(&retainor, &retainee, &100, &token.address);
+    contract.submit_bill(&retainor, &retainee, &80, &str(&env, "Bill 1"), &str(&env, "2021-01-01T00:00:00Z"));
+    contract.counter_bill(&retainor, &retainee, &60, &ReasonCode::RateDispute, &str(&env, "Too many hours"), &str(&env, "2021-01-02T00:00:00Z"));
+    contract.unsubmit_bill(&retainor, &retainee, &str(&env, "Rebilling"), &str(&env, "2021-01-03T00:00:00Z"));
+    contract.submit_bill(&retainor, &retainee, &70, &str(&env, "Bill 2"), &str(&env, "2021-01-03T00:00:00Z"));
+
+    // the counter offer was made against the withdrawn bill
+    contract.accept_counter(&retainor, &retainee, &str(&env, "2021-01-04T00:00:00Z"));
+}
+
+#[test]
+#[should_panic(expected = "No pending payment")]
+fn test_unsubmit_bill_without_pending_payment() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+
+    contract.add_retainer_balance(&retainor, &retainee, &100, &token.address);
+    contract.unsubmit_bill(&retainor, &retainee, &str(&env, "Nothing to withdraw"), &str(&env, "2021-01-01T00:00:00Z"));
+}
+
+#[test]
+#[should_panic(expected = "Reason required")]
+fn test_resolve_bill_denied_without_reason() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+
+    contract.add_retainer_balance(&retainor, &retainee, &100, &token.address);
+    contract.submit_bill(&retainor, &retainee, &50, &str(&env, "Bill 1"), &str(&env, "2021-01-01T00:00:00Z"));
+    contract.resolve_bill(&retainor, 
+                            &retainee, 
+                            &ApprovalStatus::Denied,
+                            &None,
+                            &false,
+                            &str(&env, "Bill 1 resolved"),
+                            &str(&env, "2021-01-01T00:00:00Z"));
+}
+
+
+#[test]
+fn test_standing_approval() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+
+    contract.add_retainer_balance(&retainor, &retainee, &1_000, &token.address);
+    contract.set_approval_policy(&retainor, &retainee, &200, &300, &86_400);
+    env.ledger().set_timestamp(1_000_000);
+
+    // fits the policy and is paid immediately
+    contract.submit_bill(&retainor, &retainee, &200, &str(&env, "Bill 1"), &str(&env, "2021-01-01T00:00:00Z"));
+    assert_eq!(contract.view_bill(&retainor, &retainee), None);
+    assert_eq!(token.balance(&retainee), 200);
+    assert_eq!(
+        contract.view_receipt(&retainor, &retainee, &1),
+        Some(Receipt {
+            bill: Bill {
+                amount: 200,
+                notes: str(&env, "Bill 1"),
+                date: str(&env, "2021-01-01T00:00:00Z"),
+                token: token.address.clone(),
+            },
+            notes: str(&env, "Bill 1"),
+            date: str(&env, "2021-01-01T00:00:00Z"),
+            status: ApprovalStatus::AutoApproved,
+            settled_amount: 200,
+            withheld: 0,
+            related_receipt: None,
+            payouts: vec![&env],
+            member: None,
         })
     );
-}
\ No newline at end of file
+
+    // exceeds the remaining headroom in the window and falls back to a pending payment
+    contract.submit_bill(&retainor, &retainee, &150, &str(&env, "Bill 2"), &str(&env, "2021-01-01T00:00:00Z"));
+    assert_eq!(contract.view_bill(&retainor, &retainee).unwrap().amount, 150);
+    contract.unsubmit_bill(&retainor, &retainee, &str(&env, "Wait for window"), &str(&env, "2021-01-01T00:00:00Z"));
+
+    // the earlier approval has left the window
+    env.ledger().set_timestamp(1_000_000 + 86_400);
+    contract.submit_bill(&retainor, &retainee, &150, &str(&env, "Bill 3"), &str(&env, "2021-01-02T00:00:00Z"));
+    assert_eq!(contract.view_bill(&retainor, &retainee), None);
+    assert_eq!(token.balance(&retainee), 350);
+    assert_eq!(contract.retainer_balance_unwrap(&retainor, &retainee).amount, 650);
+    assert_eq!(contract.engagement_stats(&retainor, &retainee).bills_approved, 2);
+}
+
+#[test]
+fn test_billing_cap() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+
+    env.ledger().set_timestamp(1_000_000);
+    contract.add_retainer_balance(&retainor, &retainee, &1_000, &token.address);
+    assert_eq!(contract.billing_cap_headroom(&retainor, &retainee), None);
+    contract.set_billing_cap(&retainor, &retainee, &300, &(30 * 86_400));
+
+    contract.submit_bill(&retainor, &retainee, &200, &str(&env, "Bill 1"), &str(&env, "2021-01-01T00:00:00Z"));
+    contract.resolve_bill(&retainor, 
+                            &retainee, 
+                            &ApprovalStatus::Approved,
+                            &None,
+                            &false,
+                            &str(&env, "Bill 1 resolved"),
+                            &str(&env, "2021-01-01T00:00:00Z"));
+    assert_eq!(contract.billing_cap_headroom(&retainor, &retainee), Some(100));
+
+    // the next period starts with a fresh allowance
+    env.ledger().set_timestamp(1_000_000 + 30 * 86_400);
+    assert_eq!(contract.billing_cap_headroom(&retainor, &retainee), Some(300));
+    contract.submit_bill(&retainor, &retainee, &300, &str(&env, "Bill 2"), &str(&env, "2021-02-01T00:00:00Z"));
+    contract.resolve_bill(&retainor, 
+                            &retainee, 
+                            &ApprovalStatus::Approved,
+                            &None,
+                            &false,
+                            &str(&env, "Bill 2 resolved"),
+                            &str(&env, "2021-02-01T00:00:00Z"));
+    assert_eq!(contract.billing_cap_headroom(&retainor, &retainee), Some(0));
+    assert_eq!(token.balance(&retainee), 500);
+}
+
+#[test]
+#[should_panic(expected = "Billing cap exceeded")]
+fn test_billing_cap_exceeded_on_resolve() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+
+    contract.add_retainer_balance(&retainor, &retainee, &1_000, &token.address);
+    contract.submit_bill(&retainor, &retainee, &200, &str(&env, "Bill 1"), &str(&env, "2021-01-01T00:00:00Z"));
+    // the cap is lowered while the bill is pending
+    contract.set_billing_cap(&retainor, &retainee, &100, &(30 * 86_400));
+    contract.resolve_bill(&retainor, 
+                            &retainee, 
+                            &ApprovalStatus::Approved,
+                            &None,
+                            &false,
+                            &str(&env, "Bill 1 resolved"),
+                            &str(&env, "2021-01-01T00:00:00Z"));
+}
+
+#[test]
+fn test_submit_time_bill() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+
+    env.ledger().set_timestamp(1_000_000);
+    contract.add_retainer_balance(&retainor, &retainee, &1_000, &token.address);
+    contract.set_rate(&retainor, &retainee, &symbol_short!("partner"), &120, &0);
+    contract.set_rate(&retainor, &retainee, &symbol_short!("assoc"), &60, &0);
+    // a raise that only applies from a later date
+    contract.set_rate(&retainor, &retainee, &symbol_short!("partner"), &150, &2_000_000);
+    assert_eq!(contract.current_rate(&retainor, &retainee, &symbol_short!("partner")), Some(120));
+
+    contract.submit_time_bill(&retainor, 
+                                &retainee, 
+                                &vec![&env, 
+                                    TimeEntry { code: symbol_short!("partner"), minutes: 90 },
+                                    TimeEntry { code: symbol_short!("assoc"), minutes: 120 }],
+                                &str(&env, "Bill 1"), 
+                                &str(&env, "2021-01-01T00:00:00Z"));
+    assert_eq!(contract.view_bill(&retainor, &retainee).unwrap().amount, 300);
+    contract.resolve_bill(&retainor, 
+                            &retainee, 
+                            &ApprovalStatus::Approved,
+                            &None,
+                            &false,
+                            &str(&env, "Bill 1 resolved"),
+                            &str(&env, "2021-01-01T00:00:00Z"));
+
+    env.ledger().set_timestamp(2_000_000);
+    assert_eq!(contract.current_rate(&retainor, &retainee, &symbol_short!("partner")), Some(150));
+    contract.submit_time_bill(&retainor, 
+                                &retainee, 
+                                &vec![&env, TimeEntry { code: symbol_short!("partner"), minutes: 60 }],
+                                &str(&env, "Bill 2"), 
+                                &str(&env, "2021-02-01T00:00:00Z"));
+    assert_eq!(contract.view_bill(&retainor, &retainee).unwrap().amount, 150);
+}
+
+#[test]
+fn test_submit_time_bill_rounds_once() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+
+    contract.add_retainer_balance(&retainor, &retainee, &1_000, &token.address);
+    contract.set_rate(&retainor, &retainee, &symbol_short!("partner"), &100, &0);
+    // three 20 minute calls bill a full hour, not three rounded-down thirds
+    contract.submit_time_bill(&retainor, 
+                                &retainee, 
+                                &vec![&env, 
+                                    TimeEntry { code: symbol_short!("partner"), minutes: 20 },
+                                    TimeEntry { code: symbol_short!("partner"), minutes: 20 },
+                                    TimeEntry { code: symbol_short!("partner"), minutes: 20 }],
+                                &str(&env, "Bill 1"), 
+                                &str(&env, "2021-01-01T00:00:00Z"));
+    assert_eq!(contract.view_bill(&retainor, &retainee).unwrap().amount, 100);
+}
+
+#[test]
+#[should_panic(expected = "No rate for code")]
+fn test_submit_time_bill_unknown_code() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+
+    contract.add_retainer_balance(&retainor, &retainee, &1_000, &token.address);
+    contract.set_rate(&retainor, &retainee, &symbol_short!("partner"), &120, &0);
+    contract.submit_time_bill(&retainor, 
+                                &retainee, 
+                                &vec![&env, TimeEntry { code: Symbol::new(&env, "paralegal"), minutes: 60 }],
+                                &str(&env, "Bill 1"), 
+                                &str(&env, "2021-01-01T00:00:00Z"));
+}
+
+#[test]
+fn test_budget_warnings() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+
+    contract.add_retainer_balance(&retainor, &retainee, &1_000, &token.address);
+    contract.set_budget(&retainor, &retainee, &400, &vec![&env, 7_500, 9_000, 10_000], &true);
+
+    contract.submit_bill(&retainor, &retainee, &250, &str(&env, "Bill 1"), &str(&env, "2021-01-01T00:00:00Z"));
+    assert_eq!(env.events().all().len(), 0);
+    contract.resolve_bill(&retainor, 
+                            &retainee, 
+                            &ApprovalStatus::Approved,
+                            &None,
+                            &false,
+                            &str(&env, "Bill 1 resolved"),
+                            &str(&env, "2021-01-01T00:00:00Z"));
+    assert_eq!(contract.budget(&retainor, &retainee).unwrap().spent, 250);
+
+    // crosses both the 75% and 90% thresholds
+    contract.submit_bill(&retainor, &retainee, &120, &str(&env, "Bill 2"), &str(&env, "2021-01-02T00:00:00Z"));
+    assert_eq!(
+        env.events().all(),
+        vec![&env,
+            (contract.address.clone(),
+                (symbol_short!("budget"), retainor.clone(), retainee.clone()).into_val(&env),
+                (7_500u32, 370i128, 400i128).into_val(&env)),
+            (contract.address.clone(),
+                (symbol_short!("budget"), retainor.clone(), retainee.clone()).into_val(&env),
+                (9_000u32, 370i128, 400i128).into_val(&env))]
+    );
+}
+
+#[test]
+#[should_panic(expected = "Budget exceeded")]
+fn test_budget_requires_override() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+
+    contract.add_retainer_balance(&retainor, &retainee, &1_000, &token.address);
+    contract.set_budget(&retainor, &retainee, &400, &vec![&env, 10_000], &true);
+
+    contract.submit_bill(&retainor, &retainee, &500, &str(&env, "Bill 1"), &str(&env, "2021-01-01T00:00:00Z"));
+    contract.resolve_bill(&retainor, 
+                            &retainee, 
+                            &ApprovalStatus::Approved,
+                            &None,
+                            &false,
+                            &str(&env, "Bill 1 resolved"),
+                            &str(&env, "2021-01-01T00:00:00Z"));
+}
+
+#[test]
+fn test_budget_override() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+
+    contract.add_retainer_balance(&retainor, &retainee, &1_000, &token.address);
+    contract.set_budget(&retainor, &retainee, &400, &vec![&env, 10_000], &true);
+
+    contract.submit_bill(&retainor, &retainee, &500, &str(&env, "Bill 1"), &str(&env, "2021-01-01T00:00:00Z"));
+    contract.resolve_bill(&retainor, 
+                            &retainee, 
+                            &ApprovalStatus::Approved,
+                            &None,
+                            &true,
+                            &str(&env, "Bill 1 resolved"),
+                            &str(&env, "2021-01-01T00:00:00Z"));
+    assert_eq!(token.balance(&retainee), 500);
+    assert_eq!(contract.budget(&retainor, &retainee).unwrap().spent, 500);
+}
+
+#[test]
+fn test_sweep_expired() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+
+    env.ledger().set_timestamp(1_000_000);
+    contract.add_retainer_balance(&retainor, &retainee, &500, &token.address);
+    contract.set_expiry(&retainor, &retainee, &2_000_000);
+    assert_eq!(contract.expiry(&retainor, &retainee), Some(2_000_000));
+
+    contract.submit_bill(&retainor, &retainee, &100, &str(&env, "Bill 1"), &str(&env, "2021-01-01T00:00:00Z"));
+    env.ledger().set_timestamp(2_000_000);
+    // the bill submitted before expiry can still be resolved
+    contract.resolve_bill(&retainor, 
+                            &retainee, 
+                            &ApprovalStatus::Approved,
+                            &None,
+                            &false,
+                            &str(&env, "Bill 1 resolved"),
+                            &str(&env, "2021-01-01T00:00:00Z"));
+
+    assert_eq!(contract.sweep_expired(&retainor, &retainee, &retainor), 400);
+    assert_eq!(token.balance(&retainor), 9_900);
+    assert_eq!(token.balance(&contract.address), 0);
+    assert_eq!(contract.retainer_balance_unwrap(&retainor, &retainee).amount, 0);
+}
+
+#[test]
+fn test_sweep_expires_pending_bill() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+
+    env.ledger().set_timestamp(1_000_000);
+    contract.add_retainer_balance(&retainor, &retainee, &500, &token.address);
+    contract.set_expiry(&retainor, &retainee, &2_000_000);
+    contract.submit_bill(&retainor, &retainee, &100, &str(&env, "Bill 1"), &str(&env, "2021-01-01T00:00:00Z"));
+
+    env.ledger().set_timestamp(2_000_000);
+    assert_eq!(contract.sweep_expired(&retainor, &retainee, &retainor), 500);
+    assert_eq!(contract.view_bill(&retainor, &retainee), None);
+    let receipt = contract.view_receipt(&retainor, &retainee, &1).unwrap();
+    assert_eq!(receipt.status, ApprovalStatus::Expired);
+    assert_eq!(receipt.bill.amount, 100);
+    assert_eq!(receipt.settled_amount, 0);
+    assert_eq!(token.balance(&retainor), 10_000);
+}
+
+#[test]
+#[should_panic(expected = "Retainer expired")]
+fn test_submit_bill_after_expiry() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+
+    env.ledger().set_timestamp(1_000_000);
+    contract.add_retainer_balance(&retainor, &retainee, &500, &token.address);
+    contract.set_expiry(&retainor, &retainee, &2_000_000);
+    env.ledger().set_timestamp(2_000_001);
+    contract.submit_bill(&retainor, &retainee, &100, &str(&env, "Bill 1"), &str(&env, "2021-01-01T00:00:00Z"));
+}
+
+#[test]
+#[should_panic(expected = "Retainer not expired")]
+fn test_sweep_before_expiry() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+
+    env.ledger().set_timestamp(1_000_000);
+    contract.add_retainer_balance(&retainor, &retainee, &500, &token.address);
+    contract.set_expiry(&retainor, &retainee, &2_000_000);
+    contract.sweep_expired(&retainor, &retainee, &retainor);
+}
+
+#[test]
+fn test_evergreen_replenish() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+
+    contract.add_retainer_balance(&retainor, &retainee, &500, &token.address);
+    contract.set_evergreen_target(&retainor, &retainee, &500);
+    token.approve(&retainor, &contract.address, &300, &1_000);
+
+    contract.submit_bill(&retainor, &retainee, &200, &str(&env, "Bill 1"), &str(&env, "2021-01-01T00:00:00Z"));
+    contract.resolve_bill(&retainor, 
+                            &retainee, 
+                            &ApprovalStatus::Approved,
+                            &None,
+                            &false,
+                            &str(&env, "Bill 1 resolved"),
+                            &str(&env, "2021-01-01T00:00:00Z"));
+
+    // the payout was topped back up from the allowance
+    assert_eq!(contract.retainer_balance_unwrap(&retainor, &retainee).amount, 500);
+    assert_eq!(token.balance(&retainor), 9_300);
+    assert_eq!(token.balance(&contract.address), 500);
+    assert_eq!(token.allowance(&retainor, &contract.address), 100);
+
+    // the remaining allowance can't cover the next shortfall
+    contract.submit_bill(&retainor, &retainee, &200, &str(&env, "Bill 2"), &str(&env, "2021-01-02T00:00:00Z"));
+    contract.resolve_bill(&retainor, 
+                            &retainee, 
+                            &ApprovalStatus::Approved,
+                            &None,
+                            &false,
+                            &str(&env, "Bill 2 resolved"),
+                            &str(&env, "2021-01-02T00:00:00Z"));
+    let events = env.events().all();
+    assert_eq!(
+        events.slice(events.len() - 1..),
+        vec![&env,
+            (contract.address.clone(),
+                (Symbol::new(&env, "replenish_failed"), retainor.clone(), retainee.clone()).into_val(&env),
+                (200i128, 100i128).into_val(&env))]
+    );
+    assert_eq!(contract.retainer_balance_unwrap(&retainor, &retainee).amount, 300);
+
+    token.approve(&retainor, &contract.address, &200, &1_000);
+    assert_eq!(contract.replenish(&retainor, &retainee, &retainor), 200);
+    assert_eq!(contract.retainer_balance_unwrap(&retainor, &retainee).amount, 500);
+}
+
+#[test]
+fn test_replenish_after_expiry() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+
+    env.ledger().set_timestamp(1_000_000);
+    contract.add_retainer_balance(&retainor, &retainee, &500, &token.address);
+    contract.set_evergreen_target(&retainor, &retainee, &500);
+    contract.set_expiry(&retainor, &retainee, &2_000_000);
+    token.approve(&retainor, &contract.address, &500, &1_000);
+
+    env.ledger().set_timestamp(2_000_000);
+    assert_eq!(contract.sweep_expired(&retainor, &retainee, &retainor), 500);
+    assert_eq!(contract.evergreen_target(&retainor, &retainee), None);
+    assert_eq!(contract.replenish(&retainor, &retainee, &retainor), 0);
+    assert_eq!(contract.retainer_balance_unwrap(&retainor, &retainee).amount, 0);
+    assert_eq!(token.balance(&retainor), 10_000);
+}
+
+#[test]
+fn test_allowance_funded_engagement() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+
+    contract.set_allowance_funding(&retainor, &retainee, &token.address);
+    token.approve(&retainor, &contract.address, &300, &1_000);
+
+    contract.submit_bill(&retainor, &retainee, &200, &str(&env, "Bill 1"), &str(&env, "2021-01-01T00:00:00Z"));
+    contract.resolve_bill(&retainor, 
+                            &retainee, 
+                            &ApprovalStatus::Approved,
+                            &None,
+                            &false,
+                            &str(&env, "Bill 1 resolved"),
+                            &str(&env, "2021-01-01T00:00:00Z"));
+
+    // paid straight from the retainor without passing through escrow
+    assert_eq!(token.balance(&retainor), 9_800);
+    assert_eq!(token.balance(&retainee), 200);
+    assert_eq!(token.balance(&contract.address), 0);
+    assert_eq!(token.allowance(&retainor, &contract.address), 100);
+    assert_eq!(contract.retainer_balance(&retainor, &retainee), None);
+    assert_eq!(contract.view_receipt(&retainor, &retainee, &1).unwrap().settled_amount, 200);
+
+    contract.issue_credit(&retainor, &retainee, &50, &1, &str(&env, "Overbilled"), &str(&env, "2021-01-02T00:00:00Z"));
+    assert_eq!(token.balance(&retainor), 9_850);
+    assert_eq!(token.balance(&retainee), 150);
+}
+
+#[test]
+#[should_panic(expected = "Insufficient allowance")]
+fn test_allowance_funded_bill_exceeds_allowance() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+
+    contract.set_allowance_funding(&retainor, &retainee, &token.address);
+    token.approve(&retainor, &contract.address, &100, &1_000);
+    contract.submit_bill(&retainor, &retainee, &200, &str(&env, "Bill 1"), &str(&env, "2021-01-01T00:00:00Z"));
+}
+
+#[test]
+fn test_payout_split() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+    let partner = Address::generate(&env);
+
+    contract.add_retainer_balance(&retainor, &retainee, &1_000, &token.address);
+    contract.set_payout_split(&retainor, 
+                                &retainee, 
+                                &vec![&env, 
+                                    SplitShare { recipient: retainee.clone(), bps: 6_667 },
+                                    SplitShare { recipient: partner.clone(), bps: 3_333 }]);
+    // the split only applies once the change delay has passed
+    assert_eq!(contract.payout_split(&retainor, &retainee), None);
+    env.ledger().set_timestamp(3 * 24 * 60 * 60);
+
+    contract.submit_bill(&retainor, &retainee, &100, &str(&env, "Bill 1"), &str(&env, "2021-01-01T00:00:00Z"));
+    contract.resolve_bill(&retainor, 
+                            &retainee, 
+                            &ApprovalStatus::Approved,
+                            &None,
+                            &false,
+                            &str(&env, "Bill 1 resolved"),
+                            &str(&env, "2021-01-01T00:00:00Z"));
+
+    // the rounding remainder goes to the first recipient
+    assert_eq!(token.balance(&retainee), 67);
+    assert_eq!(token.balance(&partner), 33);
+    assert_eq!(contract.retainer_balance_unwrap(&retainor, &retainee).amount, 900);
+    assert_eq!(
+        contract.view_receipt(&retainor, &retainee, &1).unwrap().payouts,
+        vec![&env,
+            Payout { recipient: retainee.clone(), amount: 67 },
+            Payout { recipient: partner.clone(), amount: 33 }]
+    );
+}
+
+#[test]
+#[should_panic(expected = "Payout split must total 10000 bps")]
+fn test_payout_split_invalid_total() {
+    let RetainerTest { env, retainor, retainee, contract, .. } = RetainerTest::setup();
+
+    contract.set_payout_split(&retainor, 
+                                &retainee, 
+                                &vec![&env, 
+                                    SplitShare { recipient: retainee.clone(), bps: 6_000 },
+                                    SplitShare { recipient: Address::generate(&env), bps: 3_000 }]);
+}
+
+#[test]
+fn test_payout_address_delay() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+    let cold_wallet = Address::generate(&env);
+
+    env.ledger().set_timestamp(1_000_000);
+    contract.add_retainer_balance(&retainor, &retainee, &1_000, &token.address);
+    contract.set_payout_address(&retainor, &retainee, &cold_wallet);
+    assert_eq!(contract.payout_address(&retainor, &retainee), retainee);
+
+    // the change has not taken effect yet
+    contract.submit_bill(&retainor, &retainee, &100, &str(&env, "Bill 1"), &str(&env, "2021-01-01T00:00:00Z"));
+    contract.resolve_bill(&retainor, 
+                            &retainee, 
+                            &ApprovalStatus::Approved,
+                            &None,
+                            &false,
+                            &str(&env, "Bill 1 resolved"),
+                            &str(&env, "2021-01-01T00:00:00Z"));
+    assert_eq!(token.balance(&retainee), 100);
+
+    env.ledger().set_timestamp(1_000_000 + 3 * 24 * 60 * 60);
+    assert_eq!(contract.payout_address(&retainor, &retainee), cold_wallet);
+    contract.submit_bill(&retainor, &retainee, &200, &str(&env, "Bill 2"), &str(&env, "2021-01-04T00:00:00Z"));
+    contract.resolve_bill(&retainor, 
+                            &retainee, 
+                            &ApprovalStatus::Approved,
+                            &None,
+                            &false,
+                            &str(&env, "Bill 2 resolved"),
+                            &str(&env, "2021-01-04T00:00:00Z"));
+    assert_eq!(token.balance(&retainee), 100);
+    assert_eq!(token.balance(&cold_wallet), 200);
+    assert_eq!(
+        contract.view_receipt(&retainor, &retainee, &2).unwrap().payouts,
+        vec![&env, Payout { recipient: cold_wallet.clone(), amount: 200 }]
+    );
+
+    // a cancelled change leaves the settled address in place
+    contract.set_payout_address(&retainor, &retainee, &Address::generate(&env));
+    contract.cancel_payout_address_change(&retainor, &retainee);
+    env.ledger().set_timestamp(2_000_000);
+    assert_eq!(contract.payout_address(&retainor, &retainee), cold_wallet);
+}
+
+#[test]
+fn test_tax_withholding() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+    let tax_authority = Address::generate(&env);
+
+    // 2021-12-31T00:00:00Z
+    env.ledger().set_timestamp(1_640_908_800);
+    contract.add_retainer_balance(&retainor, &retainee, &1_000, &token.address);
+    contract.set_withholding(&retainor, &retainee, &2_500, &tax_authority);
+
+    contract.submit_bill(&retainor, &retainee, &200, &str(&env, "Bill 1"), &str(&env, "2021-12-31T00:00:00Z"));
+    contract.resolve_bill(&retainor, 
+                            &retainee, 
+                            &ApprovalStatus::Approved,
+                            &None,
+                            &false,
+                            &str(&env, "Bill 1 resolved"),
+                            &str(&env, "2021-12-31T00:00:00Z"));
+    assert_eq!(token.balance(&retainee), 150);
+    assert_eq!(token.balance(&tax_authority), 50);
+    assert_eq!(contract.retainer_balance_unwrap(&retainor, &retainee).amount, 800);
+    assert_eq!(contract.view_receipt(&retainor, &retainee, &1).unwrap().settled_amount, 200);
+    assert_eq!(contract.view_receipt(&retainor, &retainee, &1).unwrap().withheld, 50);
+
+    // 2022-01-01T00:00:00Z
+    env.ledger().set_timestamp(1_640_995_200);
+    contract.submit_bill(&retainor, &retainee, &100, &str(&env, "Bill 2"), &str(&env, "2022-01-01T00:00:00Z"));
+    contract.resolve_bill(&retainor, 
+                            &retainee, 
+                            &ApprovalStatus::Approved,
+                            &None,
+                            &false,
+                            &str(&env, "Bill 2 resolved"),
+                            &str(&env, "2022-01-01T00:00:00Z"));
+    assert_eq!(contract.withheld_in_year(&retainor, &retainee, &2021), 50);
+    assert_eq!(contract.withheld_in_year(&retainor, &retainee, &2022), 25);
+}
+
+#[test]
+fn test_milestones() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+
+    contract.add_retainer_balance(&retainor, &retainee, &1_000, &token.address);
+    let design = contract.add_milestone(&retainor, &retainee, &str(&env, "Design"), &400, &1_700_000_000);
+    let build = contract.add_milestone(&retainor, &retainee, &str(&env, "Build"), &500, &1_800_000_000);
+    assert_eq!((design, build), (0, 1));
+
+    contract.deliver_milestone(&retainor, &retainee, &design);
+    contract.accept_milestone(&retainor, &retainee, &design, &false, &str(&env, "Looks good"), &str(&env, "2023-11-14T00:00:00Z"));
+
+    assert_eq!(token.balance(&retainee), 400);
+    assert_eq!(contract.retainer_balance_unwrap(&retainor, &retainee).amount, 600);
+    assert_eq!(
+        contract.milestones(&retainor, &retainee),
+        vec![&env,
+            Milestone {
+                description: str(&env, "Design"),
+                amount: 400,
+                due_date: 1_700_000_000,
+                status: MilestoneStatus::Accepted,
+            },
+            Milestone {
+                description: str(&env, "Build"),
+                amount: 500,
+                due_date: 1_800_000_000,
+                status: MilestoneStatus::Open,
+            }]
+    );
+    // milestones share the receipt history with ordinary bills
+    assert_eq!(
+        contract.view_receipt(&retainor, &retainee, &1),
+        Some(Receipt {
+            bill: Bill {
+                amount: 400,
+                notes: str(&env, "Design"),
+                date: str(&env, "2023-11-14T00:00:00Z"),
+                token: token.address.clone(),
+            },
+            notes: str(&env, "Looks good"),
+            date: str(&env, "2023-11-14T00:00:00Z"),
+            status: ApprovalStatus::Milestone,
+            settled_amount: 400,
+            withheld: 0,
+            related_receipt: None,
+            payouts: vec![&env],
+            member: None,
+        })
+    );
+}
+
+#[test]
+#[should_panic(expected = "Budget exceeded")]
+fn test_milestone_over_budget() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+
+    contract.add_retainer_balance(&retainor, &retainee, &1_000, &token.address);
+    contract.set_budget(&retainor, &retainee, &100, &vec![&env, 10_000], &true);
+    let design = contract.add_milestone(&retainor, &retainee, &str(&env, "Design"), &500, &1_700_000_000);
+    contract.deliver_milestone(&retainor, &retainee, &design);
+    contract.accept_milestone(&retainor, &retainee, &design, &false, &str(&env, "Looks good"), &str(&env, "2023-11-14T00:00:00Z"));
+}
+
+#[test]
+#[should_panic(expected = "Insufficient retained balance")]
+fn test_bill_cannot_use_milestone_funds() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+
+    contract.add_retainer_balance(&retainor, &retainee, &1_000, &token.address);
+    contract.add_milestone(&retainor, &retainee, &str(&env, "Design"), &800, &1_700_000_000);
+    contract.submit_bill(&retainor, &retainee, &300, &str(&env, "Bill 1"), &str(&env, "2021-01-01T00:00:00Z"));
+}
+
+#[test]
+fn test_stream_claim_and_cancel() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+
+    env.ledger().set_timestamp(1_000);
+    contract.add_retainer_balance(&retainor, &retainee, &5_000, &token.address);
+    contract.start_stream(&retainor, &retainee, &2, &1_000, &3_000);
+
+    env.ledger().set_timestamp(1_500);
+    assert_eq!(contract.stream_claimable(&retainor, &retainee), 1_000);
+    assert_eq!(contract.claim_stream(&retainor, &retainee, &str(&env, "2021-01-01T00:00:00Z")), 1_000);
+    assert_eq!(token.balance(&retainee), 1_000);
+    assert_eq!(contract.view_receipt(&retainor, &retainee, &1).unwrap().status, ApprovalStatus::Streamed);
+
+    env.ledger().set_timestamp(2_000);
+    assert_eq!(contract.cancel_stream(&retainor, &retainee, &str(&env, "2021-01-02T00:00:00Z")), 2_000);
+    assert_eq!(token.balance(&retainee), 2_000);
+    assert_eq!(token.balance(&retainor), 7_000);
+    assert_eq!(contract.retainer_balance_unwrap(&retainor, &retainee).amount, 1_000);
+    assert_eq!(contract.stream(&retainor, &retainee), None);
+    assert_eq!(contract.history_index(&retainor, &retainee), 2);
+}
+
+#[test]
+#[should_panic(expected = "Insufficient retained balance")]
+fn test_stream_funds_are_committed() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+
+    env.ledger().set_timestamp(1_000);
+    contract.add_retainer_balance(&retainor, &retainee, &5_000, &token.address);
+    contract.start_stream(&retainor, &retainee, &2, &1_000, &3_000);
+    contract.remove_retainer_balance(&retainor, &retainee, &1_001);
+}
+
+#[test]
+fn test_process_due_payments() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+
+    env.ledger().set_timestamp(1_000);
+    contract.add_retainer_balance(&retainor, &retainee, &250, &token.address);
+    contract.set_payment_schedule(&retainor, &retainee, &100, &1_000, &4, &2_000);
+
+    assert_eq!(contract.process_due_payments(&retainor, &retainee, &retainee), 0);
+
+    // two installments are due and both can be paid
+    env.ledger().set_timestamp(3_000);
+    assert_eq!(contract.process_due_payments(&retainor, &retainee, &retainee), 2);
+    assert_eq!(token.balance(&retainee), 200);
+
+    // the third can't be covered and is skipped
+    env.ledger().set_timestamp(4_000);
+    assert_eq!(contract.process_due_payments(&retainor, &retainee, &retainee), 0);
+    assert_eq!(token.balance(&retainee), 200);
+    let receipt = contract.view_receipt(&retainor, &retainee, &3).unwrap();
+    assert_eq!(receipt.status, ApprovalStatus::Skipped);
+    assert_eq!(receipt.settled_amount, 0);
+
+    contract.add_retainer_balance(&retainor, &retainee, &50, &token.address);
+    env.ledger().set_timestamp(10_000);
+    assert_eq!(contract.process_due_payments(&retainor, &retainee, &retainee), 1);
+    assert_eq!(
+        contract.payment_schedule(&retainor, &retainee),
+        Some(PaymentSchedule {
+            amount: 100,
+            interval: 1_000,
+            count: 4,
+            next_due: 6_000,
+            paid: 3,
+            skipped: 1,
+        })
+    );
+    assert_eq!(contract.view_receipt(&retainor, &retainee, &4).unwrap().status, ApprovalStatus::Scheduled);
+    assert_eq!(contract.retainer_balance_unwrap(&retainor, &retainee).amount, 0);
+}
+
+#[test]
+fn test_process_due_payments_in_batches() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+
+    env.ledger().set_timestamp(1_000);
+    contract.add_retainer_balance(&retainor, &retainee, &1_000, &token.address);
+    contract.set_payment_schedule(&retainor, &retainee, &10, &1, &60, &1_000);
+
+    env.ledger().set_timestamp(2_000);
+    assert_eq!(contract.process_due_payments(&retainor, &retainee, &retainee), 25);
+    assert_eq!(contract.process_due_payments(&retainor, &retainee, &retainee), 25);
+    assert_eq!(contract.process_due_payments(&retainor, &retainee, &retainee), 10);
+    assert_eq!(contract.process_due_payments(&retainor, &retainee, &retainee), 0);
+    assert_eq!(token.balance(&retainee), 600);
+}
+
+#[test]
+#[should_panic(expected = "Invalid schedule")]
+fn test_payment_schedule_overflows() {
+    let RetainerTest { env: _, retainor, retainee, contract, token } = RetainerTest::setup();
+
+    contract.add_retainer_balance(&retainor, &retainee, &1_000, &token.address);
+    contract.set_payment_schedule(&retainor, &retainee, &10, &u64::MAX, &2, &1_000);
+}
+
+#[test]
+fn test_keeper_reward() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+    let keeper = Address::generate(&env);
+
+    env.ledger().set_timestamp(1_000);
+    contract.add_retainer_balance(&retainor, &retainee, &1_000, &token.address);
+    contract.fund_keeper_reward(&retainor, &retainee, &25, &10);
+    contract.set_payment_schedule(&retainor, &retainee, &100, &1_000, &12, &2_000);
+    assert_eq!(token.balance(&contract.address), 1_025);
+
+    // nothing due yet, so no reward
+    assert_eq!(contract.process_due_payments(&retainor, &retainee, &keeper), 0);
+    assert_eq!(token.balance(&keeper), 0);
+
+    env.ledger().set_timestamp(2_000);
+    assert_eq!(contract.process_due_payments(&retainor, &retainee, &keeper), 1);
+    assert_eq!(token.balance(&keeper), 10);
+    env.ledger().set_timestamp(3_000);
+    contract.process_due_payments(&retainor, &retainee, &keeper);
+    env.ledger().set_timestamp(4_000);
+    contract.process_due_payments(&retainor, &retainee, &keeper);
+
+    // the fund no longer covers a full reward
+    assert_eq!(token.balance(&keeper), 20);
+    assert_eq!(contract.keeper_fund(&retainor, &retainee).unwrap().balance, 5);
+    assert_eq!(contract.withdraw_keeper_fund(&retainor, &retainee), 5);
+    assert_eq!(contract.retainer_balance_unwrap(&retainor, &retainee).amount, 700);
+}
+
+#[test]
+fn test_keeper_reward_not_paid_for_skips() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+    let keeper = Address::generate(&env);
+
+    env.ledger().set_timestamp(1_000);
+    contract.add_retainer_balance(&retainor, &retainee, &50, &token.address);
+    contract.fund_keeper_reward(&retainor, &retainee, &100, &10);
+    contract.set_payment_schedule(&retainor, &retainee, &100, &1_000, &12, &2_000);
+
+    env.ledger().set_timestamp(2_000);
+    assert_eq!(contract.process_due_payments(&retainor, &retainee, &keeper), 0);
+    assert_eq!(contract.payment_schedule(&retainor, &retainee).unwrap().skipped, 1);
+    assert_eq!(token.balance(&keeper), 0);
+}
+
+#[test]
+fn test_keeper_reward_sweep_then_replenish() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+    let keeper = Address::generate(&env);
+
+    env.ledger().set_timestamp(1_000_000);
+    contract.add_retainer_balance(&retainor, &retainee, &500, &token.address);
+    contract.set_evergreen_target(&retainor, &retainee, &500);
+    contract.set_expiry(&retainor, &retainee, &2_000_000);
+    contract.fund_keeper_reward(&retainor, &retainee, &100, &10);
+    token.approve(&retainor, &contract.address, &5_000, &1_000);
+
+    env.ledger().set_timestamp(2_000_000);
+    for _ in 0..5 {
+        contract.sweep_expired(&retainor, &retainee, &keeper);
+        contract.replenish(&retainor, &retainee, &keeper);
+    }
+    // only the first sweep moved anything
+    assert_eq!(token.balance(&keeper), 10);
+    assert_eq!(contract.retainer_balance_unwrap(&retainor, &retainee).amount, 0);
+}
+
+#[test]
+fn test_bond_slash_and_close() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+    let arbiter = Address::generate(&env);
+
+    token.transfer(&retainor, &retainee, &500);
+    contract.add_retainer_balance(&retainor, &retainee, &1_000, &token.address);
+    contract.require_bond(&retainor, &retainee, &300, &arbiter);
+    contract.post_bond(&retainor, &retainee, &300);
+    assert_eq!(token.balance(&contract.address), 1_300);
+
+    contract.slash_bond(&retainor, &retainee, &100, &str(&env, "Overbilling upheld"), &str(&env, "2021-01-01T00:00:00Z"));
+    assert_eq!(token.balance(&retainor), 8_600);
+    assert_eq!(contract.bond(&retainor, &retainee).unwrap().posted, 200);
+    assert_eq!(contract.view_receipt(&retainor, &retainee, &1).unwrap().status, ApprovalStatus::Slashed);
+
+    contract.close_engagement(&retainor, &retainee);
+    assert_eq!(token.balance(&retainor), 9_600);
+    assert_eq!(token.balance(&retainee), 400);
+    assert_eq!(token.balance(&contract.address), 0);
+    assert_eq!(contract.bond(&retainor, &retainee), None);
+}
+
+#[test]
+#[should_panic(expected = "Bond not posted")]
+fn test_submit_bill_without_bond() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+
+    contract.add_retainer_balance(&retainor, &retainee, &1_000, &token.address);
+    contract.require_bond(&retainor, &retainee, &300, &Address::generate(&env));
+    contract.submit_bill(&retainor, &retainee, &100, &str(&env, "Bill 1"), &str(&env, "2021-01-01T00:00:00Z"));
+}
+
+#[test]
+fn test_assign_engagement_to_new_retainee() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+    let incorporated = Address::generate(&env);
+
+    contract.add_retainer_balance(&retainor, &retainee, &1_000, &token.address);
+    contract.submit_bill(&retainor, &retainee, &100, &str(&env, "Bill 1"), &str(&env, "2021-01-01T00:00:00Z"));
+    contract.resolve_bill(&retainor, &retainee, &ApprovalStatus::Approved, &None, &false, &str(&env, "Approved"), &str(&env, "2021-01-02T00:00:00Z"));
+
+    contract.assign_engagement(&retainor, &retainee, &retainor, &incorporated, &str(&env, "Incorporated"), &str(&env, "2021-02-01T00:00:00Z"));
+
+    assert_eq!(contract.retainer_balance(&retainor, &retainee), None);
+    assert_eq!(contract.history_index(&retainor, &retainee), 0);
+    assert_eq!(contract.retainer_balance_unwrap(&retainor, &incorporated).amount, 900);
+    assert_eq!(contract.history_index(&retainor, &incorporated), 2);
+    assert_eq!(contract.view_receipt(&retainor, &incorporated, &1).unwrap().status, ApprovalStatus::Approved);
+    assert_eq!(contract.view_receipt(&retainor, &incorporated, &2).unwrap().status, ApprovalStatus::Assigned);
+    assert_eq!(contract.engagement_stats(&retainor, &incorporated).amount_paid, 100);
+
+    assert_eq!(contract.retainor_info(&retainor).retainees, vec![&env, incorporated.clone()]);
+    assert_eq!(contract.retainee_info(&retainee).retainors, vec![&env]);
+    let info = contract.retainee_info(&incorporated);
+    assert_eq!(info.name, str(&env, "Alice"));
+    assert_eq!(info.retainors, vec![&env, retainor.clone()]);
+
+    contract.submit_bill(&retainor, &incorporated, &100, &str(&env, "Bill 2"), &str(&env, "2021-02-02T00:00:00Z"));
+    contract.resolve_bill(&retainor, &incorporated, &ApprovalStatus::Approved, &None, &false, &str(&env, "Approved"), &str(&env, "2021-02-03T00:00:00Z"));
+    assert_eq!(token.balance(&incorporated), 100);
+}
+
+#[test]
+#[should_panic(expected = "Exactly one party must change")]
+fn test_assign_engagement_both_parties() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+
+    contract.add_retainer_balance(&retainor, &retainee, &1_000, &token.address);
+    contract.assign_engagement(&retainor, &retainee, &Address::generate(&env), &Address::generate(&env), &str(&env, "Both"), &str(&env, "2021-02-01T00:00:00Z"));
+}
+
+#[test]
+fn test_reallocate_between_retainees() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+    let other = Address::generate(&env);
+
+    contract.add_retainer_balance(&retainor, &retainee, &1_000, &token.address);
+    contract.add_milestone(&retainor, &retainee, &str(&env, "Draft"), &600, &0);
+    contract.reallocate(&retainor, &retainee, &other, &400);
+
+    assert_eq!(contract.retainer_balance_unwrap(&retainor, &retainee).amount, 600);
+    assert_eq!(contract.retainer_balance_unwrap(&retainor, &other).amount, 400);
+    assert_eq!(token.balance(&contract.address), 1_000);
+    assert_eq!(token.balance(&retainor), 9_000);
+    assert_eq!(contract.engagement_stats(&retainor, &other).amount_deposited, 400);
+}
+
+#[test]
+#[should_panic(expected = "Insufficient retained balance")]
+fn test_reallocate_committed_funds() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+    let other = Address::generate(&env);
+
+    contract.add_retainer_balance(&retainor, &retainee, &1_000, &token.address);
+    contract.add_milestone(&retainor, &retainee, &str(&env, "Draft"), &600, &0);
+    contract.reallocate(&retainor, &retainee, &other, &401);
+}
+
+#[test]
+#[should_panic(expected = "Pending payment exists")]
+fn test_reallocate_with_pending_bill() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+    let other = Address::generate(&env);
+
+    contract.add_retainer_balance(&retainor, &retainee, &1_000, &token.address);
+    contract.submit_bill(&retainor, &retainee, &100, &str(&env, "Bill 1"), &str(&env, "2021-01-01T00:00:00Z"));
+    contract.reallocate(&retainor, &retainee, &other, &100);
+}
+
+#[test]
+#[should_panic(expected = "Member has its own retainer")]
+fn test_reallocate_to_pool_member() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+    let firm = Address::generate(&env);
+
+    contract.add_retainer_balance(&retainor, &retainee, &1_000, &token.address);
+    contract.add_retainer_balance(&retainor, &firm, &1_000, &token.address);
+    let associate = Address::generate(&env);
+    contract.set_pool_member(&retainor, &firm, &associate, &None);
+    contract.reallocate(&retainor, &retainee, &associate, &100);
+}
+
+#[test]
+#[should_panic(expected = "Retainer expired")]
+fn test_reallocate_to_expired_retainer() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+    let other = Address::generate(&env);
+
+    contract.add_retainer_balance(&retainor, &retainee, &1_000, &token.address);
+    contract.add_retainer_balance(&retainor, &other, &100, &token.address);
+    contract.set_expiry(&retainor, &other, &1_000);
+    env.ledger().set_timestamp(1_000);
+    contract.reallocate(&retainor, &retainee, &other, &100);
+}
+
+#[test]
+fn test_pool_members_bill_shared_balance() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+    let firm = Address::generate(&env);
+    let associate = Address::generate(&env);
+
+    contract.add_retainer_balance(&retainor, &firm, &1_000, &token.address);
+    contract.set_pool_member(&retainor, &firm, &retainee, &None);
+    contract.set_pool_member(&retainor, &firm, &associate, &Some(150));
+    assert_eq!(contract.member_pool(&retainor, &associate), Some(firm.clone()));
+
+    contract.submit_bill(&retainor, &retainee, &300, &str(&env, "Partner hours"), &str(&env, "2021-01-01T00:00:00Z"));
+    contract.resolve_bill(&retainor, &retainee, &ApprovalStatus::Approved, &None, &false, &str(&env, "Approved"), &str(&env, "2021-01-02T00:00:00Z"));
+    contract.submit_bill(&retainor, &associate, &100, &str(&env, "Research"), &str(&env, "2021-01-03T00:00:00Z"));
+    contract.resolve_bill(&retainor, &associate, &ApprovalStatus::Approved, &None, &false, &str(&env, "Approved"), &str(&env, "2021-01-04T00:00:00Z"));
+
+    assert_eq!(contract.retainer_balance_unwrap(&retainor, &firm).amount, 600);
+    assert_eq!(token.balance(&retainee), 300);
+    assert_eq!(token.balance(&associate), 100);
+    assert_eq!(contract.view_receipt(&retainor, &associate, &1).unwrap().bill.notes, str(&env, "Research"));
+    assert_eq!(contract.pool_members(&retainor, &firm), vec![
+        &env,
+        PoolMember { member: retainee.clone(), limit: None, spent: 300 },
+        PoolMember { member: associate.clone(), limit: Some(150), spent: 100 },
+    ]);
+
+    // the pool's history shows which member billed
+    assert_eq!(contract.view_receipt(&retainor, &firm, &1).unwrap().member, Some(retainee.clone()));
+    let receipt = contract.view_receipt(&retainor, &firm, &2).unwrap();
+    assert_eq!(receipt.member, Some(associate.clone()));
+    assert_eq!(receipt.bill.notes, str(&env, "Research"));
+}
+
+#[test]
+#[should_panic(expected = "Exceeds member limit")]
+fn test_pool_member_limit() {
+    let RetainerTest { env, retainor, retainee: _, contract, token } = RetainerTest::setup();
+    let firm = Address::generate(&env);
+    let associate = Address::generate(&env);
+
+    contract.add_retainer_balance(&retainor, &firm, &1_000, &token.address);
+    contract.set_pool_member(&retainor, &firm, &associate, &Some(150));
+    contract.submit_bill(&retainor, &associate, &100, &str(&env, "Research"), &str(&env, "2021-01-03T00:00:00Z"));
+    contract.resolve_bill(&retainor, &associate, &ApprovalStatus::Approved, &None, &false, &str(&env, "Approved"), &str(&env, "2021-01-04T00:00:00Z"));
+    contract.submit_bill(&retainor, &associate, &100, &str(&env, "Over limit"), &str(&env, "2021-01-05T00:00:00Z"));
+}
+
+#[test]
+#[should_panic(expected = "Budget exceeded")]
+fn test_pool_budget_applies_to_members() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+    let firm = Address::generate(&env);
+
+    contract.add_retainer_balance(&retainor, &firm, &1_000, &token.address);
+    contract.set_pool_member(&retainor, &firm, &retainee, &None);
+    contract.set_budget(&retainor, &firm, &400, &vec![&env, 5_000], &true);
+
+    contract.submit_bill(&retainor, &retainee, &300, &str(&env, "Bill 1"), &str(&env, "2021-01-01T00:00:00Z"));
+    let events = env.events().all();
+    assert_eq!(
+        events.slice(events.len() - 1..),
+        vec![&env,
+            (contract.address.clone(),
+                (symbol_short!("budget"), retainor.clone(), firm.clone()).into_val(&env),
+                (5_000u32, 300i128, 400i128).into_val(&env))]
+    );
+    contract.resolve_bill(&retainor, &retainee, &ApprovalStatus::Approved, &None, &false, &str(&env, "Approved"), &str(&env, "2021-01-02T00:00:00Z"));
+    assert_eq!(contract.budget(&retainor, &firm).unwrap().spent, 300);
+
+    contract.submit_bill(&retainor, &retainee, &200, &str(&env, "Bill 2"), &str(&env, "2021-01-03T00:00:00Z"));
+    contract.resolve_bill(&retainor, &retainee, &ApprovalStatus::Approved, &None, &false, &str(&env, "Approved"), &str(&env, "2021-01-04T00:00:00Z"));
+}
+
+#[test]
+fn test_sponsor_refunds_pro_rata() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+    let sponsor = Address::generate(&env);
+    TokenAdminClient::new(&env, &token.address).mint(&sponsor, &3_000);
+
+    contract.add_retainer_balance(&retainor, &retainee, &1_000, &token.address);
+    contract.sponsor_retainer(&sponsor, &retainor, &retainee, &3_000);
+    assert_eq!(contract.retainer_balance_unwrap(&retainor, &retainee).amount, 4_000);
+
+    contract.remove_retainer_balance(&retainor, &retainee, &400);
+    assert_eq!(token.balance(&retainor), 9_100);
+    assert_eq!(token.balance(&sponsor), 300);
+    assert_eq!(contract.contributions(&retainor, &retainee), vec![
+        &env,
+        Contribution { funder: retainor.clone(), amount: 900 },
+        Contribution { funder: sponsor.clone(), amount: 2_700 },
+    ]);
+}
+
+#[test]
+#[should_panic(expected = "Engagement has sponsors")]
+fn test_reallocate_sponsored_funds() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+    let sponsor = Address::generate(&env);
+    TokenAdminClient::new(&env, &token.address).mint(&sponsor, &3_000);
+
+    contract.add_retainer_balance(&retainor, &retainee, &1_000, &token.address);
+    contract.sponsor_retainer(&sponsor, &retainor, &retainee, &3_000);
+    contract.reallocate(&retainor, &retainee, &Address::generate(&env), &100);
+}
+
+#[test]
+fn test_sponsor_refund_after_payout() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+    let sponsor = Address::generate(&env);
+    TokenAdminClient::new(&env, &token.address).mint(&sponsor, &100);
+
+    contract.add_retainer_balance(&retainor, &retainee, &100, &token.address);
+    contract.submit_bill(&retainor, &retainee, &100, &str(&env, "Bill 1"), &str(&env, "2021-01-01T00:00:00Z"));
+    contract.resolve_bill(&retainor, 
+                            &retainee, 
+                            &ApprovalStatus::Approved,
+                            &None,
+                            &false,
+                            &str(&env, "Bill 1 resolved"),
+                            &str(&env, "2021-01-01T00:00:00Z"));
+    contract.sponsor_retainer(&sponsor, &retainor, &retainee, &100);
+
+    // the retainor's deposit was spent, so all that is left belongs to the sponsor
+    contract.close_engagement(&retainor, &retainee);
+    assert_eq!(token.balance(&sponsor), 100);
+    assert_eq!(token.balance(&retainor), 9_900);
+    assert_eq!(token.balance(&contract.address), 0);
+}
+
+#[test]
+fn test_sponsor_refunds_sponsors_first() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+    let sponsor = Address::generate(&env);
+    TokenAdminClient::new(&env, &token.address).mint(&sponsor, &500);
+
+    contract.add_retainer_balance(&retainor, &retainee, &1_000, &token.address);
+    contract.sponsor_retainer(&sponsor, &retainor, &retainee, &500);
+    contract.set_refund_rule(&retainor, &retainee, &RefundRule::SponsorsFirst);
+
+    contract.close_engagement(&retainor, &retainee);
+    assert_eq!(token.balance(&sponsor), 500);
+    assert_eq!(token.balance(&retainor), 10_000);
+    assert_eq!(token.balance(&contract.address), 0);
+}
+
+#[test]
+fn test_resolve_bill_signed_by_relayer() {
+    use ed25519_dalek::{Signer, SigningKey};
+
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+    let signer = SigningKey::from_bytes(&[7; 32]);
+    contract.set_signing_key(&retainor, &BytesN::from_array(&env, &signer.verifying_key().to_bytes()));
+    let sign = |approval: &SignedApproval, reason: &Option<ReasonCode>| {
+        let message = contract.approval_message(approval, reason);
+        let mut buf = [0u8; 1024];
+        let len = message.len() as usize;
+        message.copy_into_slice(&mut buf[..len]);
+        BytesN::from_array(&env, &signer.sign(&buf[..len]).to_bytes())
+    };
+
+    contract.add_retainer_balance(&retainor, &retainee, &1_000, &token.address);
+    contract.submit_bill(&retainor, &retainee, &100, &str(&env, "Bill 1"), &str(&env, "2021-01-01T00:00:00Z"));
+
+    let approval = SignedApproval {
+        retainor: retainor.clone(),
+        retainee: retainee.clone(),
+        bill_hash: contract.bill_hash(&retainor, &retainee),
+        status: ApprovalStatus::Approved,
+        notes: str(&env, "Approved by email"),
+        date: str(&env, "2021-01-02T00:00:00Z"),
+        nonce: contract.signer_nonce(&retainor),
+        expiry: 1_000,
+    };
+    let signature = sign(&approval, &None);
+
+    env.set_auths(&[]);
+    contract.resolve_bill_signed(&approval, &None, &signature);
+    assert_eq!(token.balance(&retainee), 100);
+    assert_eq!(contract.signer_nonce(&retainor), 1);
+    let receipt = contract.view_receipt(&retainor, &retainee, &1).unwrap();
+    assert_eq!(receipt.status, ApprovalStatus::Approved);
+    assert_eq!(receipt.notes, str(&env, "Approved by email"));
+
+    // the same approval can't be replayed against a new bill
+    env.mock_all_auths();
+    contract.submit_bill(&retainor, &retainee, &100, &str(&env, "Bill 2"), &str(&env, "2021-01-03T00:00:00Z"));
+    let result = contract.try_resolve_bill_signed(&approval, &None, &signature);
+    assert!(result.is_err());
+
+    // nor can the relayer change the signed denial reason
+    let denial = SignedApproval {
+        bill_hash: contract.bill_hash(&retainor, &retainee),
+        status: ApprovalStatus::Denied,
+        notes: str(&env, "Duplicate of bill 1"),
+        nonce: 1,
+        ..approval
+    };
+    let signature = sign(&denial, &Some(ReasonCode::Duplicate));
+    let result = contract.try_resolve_bill_signed(&denial, &Some(ReasonCode::Other), &signature);
+    assert!(result.is_err());
+    contract.resolve_bill_signed(&denial, &Some(ReasonCode::Duplicate), &signature);
+    assert_eq!(contract.receipt_reason(&retainor, &retainee, &2), Some(ReasonCode::Duplicate));
+}
+
+#[test]
+fn test_pending_bill_is_committed() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+
+    contract.add_retainer_balance(&retainor, &retainee, &100, &token.address);
+    contract.submit_bill(&retainor, &retainee, &100, &str(&env, "Bill 1"), &str(&env, "2021-01-01T00:00:00Z"));
+    let result = contract.try_add_milestone(&retainor, &retainee, &str(&env, "Draft"), &100, &0);
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_sweep_expired_keeps_commitments() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+
+    contract.add_retainer_balance(&retainor, &retainee, &500, &token.address);
+    contract.add_milestone(&retainor, &retainee, &str(&env, "Draft"), &60, &0);
+    contract.set_expiry(&retainor, &retainee, &1_000);
+    env.ledger().set_timestamp(1_000);
+
+    assert_eq!(contract.sweep_expired(&retainor, &retainee, &retainor), 440);
+    contract.deliver_milestone(&retainor, &retainee, &0);
+    contract.accept_milestone(&retainor, &retainee, &0, &false, &str(&env, "Accepted"), &str(&env, "2021-01-01T00:00:00Z"));
+    assert_eq!(contract.retainer_balance_unwrap(&retainor, &retainee).amount, 0);
+    assert_eq!(token.balance(&retainee), 60);
+}
+
+#[test]
+fn test_installment_skipped_while_bill_pending() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+
+    contract.add_retainer_balance(&retainor, &retainee, &100, &token.address);
+    contract.set_payment_schedule(&retainor, &retainee, &100, &1_000, &1, &0);
+    contract.submit_bill(&retainor, &retainee, &100, &str(&env, "Bill 1"), &str(&env, "2021-01-01T00:00:00Z"));
+
+    assert_eq!(contract.process_due_payments(&retainor, &retainee, &retainee), 0);
+    contract.resolve_bill(&retainor, &retainee, &ApprovalStatus::Approved, &None, &false, &str(&env, "Approved"), &str(&env, "2021-01-02T00:00:00Z"));
+    assert_eq!(contract.retainer_balance_unwrap(&retainor, &retainee).amount, 0);
+    assert_eq!(token.balance(&retainee), 100);
+}
+
+#[test]
+#[should_panic(expected = "Insufficient retained balance")]
+fn test_pool_reserves_member_bills() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+    let firm = Address::generate(&env);
+    let associate = Address::generate(&env);
+
+    contract.add_retainer_balance(&retainor, &firm, &100, &token.address);
+    contract.set_pool_member(&retainor, &firm, &retainee, &None);
+    contract.set_pool_member(&retainor, &firm, &associate, &None);
+
+    contract.submit_bill(&retainor, &retainee, &80, &str(&env, "Bill 1"), &str(&env, "2021-01-01T00:00:00Z"));
+    contract.submit_bill(&retainor, &associate, &80, &str(&env, "Bill 2"), &str(&env, "2021-01-01T00:00:00Z"));
+}
+
+#[test]
+#[should_panic(expected = "Pending payment exists")]
+fn test_pool_withdrawal_blocked_by_member_bill() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+    let firm = Address::generate(&env);
+
+    contract.add_retainer_balance(&retainor, &firm, &100, &token.address);
+    contract.set_pool_member(&retainor, &firm, &retainee, &None);
+    contract.submit_bill(&retainor, &retainee, &80, &str(&env, "Bill 1"), &str(&env, "2021-01-01T00:00:00Z"));
+    contract.remove_retainer_balance(&retainor, &firm, &10);
+}
+
+#[test]
+#[should_panic(expected = "Retainer expired")]
+fn test_pool_expiry_applies_to_members() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+    let firm = Address::generate(&env);
+
+    contract.add_retainer_balance(&retainor, &firm, &100, &token.address);
+    contract.set_pool_member(&retainor, &firm, &retainee, &None);
+    contract.set_expiry(&retainor, &firm, &1_000);
+    env.ledger().set_timestamp(1_000);
+    contract.submit_bill(&retainor, &retainee, &10, &str(&env, "Bill 1"), &str(&env, "2021-01-03T00:00:00Z"));
+}
+
+#[test]
+fn test_stream_not_held_by_billing_cap() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+
+    contract.add_retainer_balance(&retainor, &retainee, &1_000, &token.address);
+    contract.set_billing_cap(&retainor, &retainee, &50, &1_000_000);
+    contract.start_stream(&retainor, &retainee, &1, &0, &1_000);
+
+    env.ledger().set_timestamp(100);
+    assert_eq!(contract.claim_stream(&retainor, &retainee, &str(&env, "2021-01-01T00:00:00Z")), 100);
+    env.ledger().set_timestamp(300);
+    assert_eq!(contract.cancel_stream(&retainor, &retainee, &str(&env, "2021-01-02T00:00:00Z")), 700);
+    assert_eq!(token.balance(&retainee), 300);
+    assert_eq!(token.balance(&retainor), 9_700);
+}
+
+#[test]
+#[should_panic(expected = "No retained balance")]
+fn test_sponsor_cannot_open_retainer() {
+    let RetainerTest { env, retainor, retainee, contract, token: _ } = RetainerTest::setup();
+    let griefer = Address::generate(&env);
+
+    contract.sponsor_retainer(&griefer, &retainor, &retainee, &1);
+}
+
+#[test]
+fn test_payout_split_change_delayed() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+    let attacker = Address::generate(&env);
+
+    contract.add_retainer_balance(&retainor, &retainee, &1_000, &token.address);
+    contract.set_payout_split(&retainor, &retainee, &vec![&env, SplitShare { recipient: attacker.clone(), bps: 10_000 }]);
+
+    contract.submit_bill(&retainor, &retainee, &100, &str(&env, "Bill 1"), &str(&env, "2021-01-01T00:00:00Z"));
+    contract.resolve_bill(&retainor, &retainee, &ApprovalStatus::Approved, &None, &false, &str(&env, "Approved"), &str(&env, "2021-01-02T00:00:00Z"));
+    assert_eq!(token.balance(&attacker), 0);
+    assert_eq!(token.balance(&retainee), 100);
+
+    // the real retainee spots the change and cancels it before it takes effect
+    contract.cancel_payout_split_change(&retainor, &retainee);
+    env.ledger().set_timestamp(3 * 24 * 60 * 60);
+    assert_eq!(contract.payout_split(&retainor, &retainee), None);
+    assert_eq!(contract.payout_split_change(&retainor, &retainee), None);
+}
+
+#[test]
+fn test_assign_engagement_keeps_withholding_years() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+    let tax_office = Address::generate(&env);
+    let incorporated = Address::generate(&env);
+
+    contract.add_retainer_balance(&retainor, &retainee, &1_000, &token.address);
+    contract.set_withholding(&retainor, &retainee, &1_000, &tax_office);
+    for (timestamp, year) in [(1_577_836_800, 2020), (1_609_459_200, 2021), (1_640_995_200, 2022)] {
+        env.ledger().set_timestamp(timestamp);
+        contract.submit_bill(&retainor, &retainee, &100, &str(&env, "Bill"), &str(&env, "2020-01-01T00:00:00Z"));
+        contract.resolve_bill(&retainor, &retainee, &ApprovalStatus::Approved, &None, &false, &str(&env, "Approved"), &str(&env, "2020-01-02T00:00:00Z"));
+        assert_eq!(contract.withheld_in_year(&retainor, &retainee, &year), 10);
+    }
+
+    contract.assign_engagement(&retainor, &retainee, &retainor, &incorporated, &str(&env, "Incorporated"), &str(&env, "2022-02-01T00:00:00Z"));
+    for year in [2020, 2021, 2022] {
+        assert_eq!(contract.withheld_in_year(&retainor, &incorporated, &year), 10);
+        assert_eq!(contract.withheld_in_year(&retainor, &retainee, &year), 0);
+    }
+}
+
+#[test]
+#[should_panic(expected = "Engagement already exists")]
+fn test_assign_engagement_onto_configured_pair() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+    let incorporated = Address::generate(&env);
+
+    contract.add_retainer_balance(&retainor, &retainee, &1_000, &token.address);
+    contract.set_allowance_funding(&retainor, &incorporated, &token.address);
+    contract.assign_engagement(&retainor, &retainee, &retainor, &incorporated, &str(&env, "Incorporated"), &str(&env, "2021-02-01T00:00:00Z"));
+}
+
+#[test]
+#[should_panic(expected = "Pool engagements can't be assigned")]
+fn test_assign_pool_engagement() {
+    let RetainerTest { env, retainor, retainee: _, contract, token } = RetainerTest::setup();
+    let firm = Address::generate(&env);
+
+    contract.add_retainer_balance(&retainor, &firm, &1_000, &token.address);
+    contract.set_pool_member(&retainor, &firm, &Address::generate(&env), &None);
+    contract.assign_engagement(&retainor, &firm, &Address::generate(&env), &firm, &str(&env, "Acquired"), &str(&env, "2021-02-01T00:00:00Z"));
+}
+
+#[test]
+fn test_sweep_expired_returns_bond() {
+    let RetainerTest { env, retainor, retainee, contract, token } = RetainerTest::setup();
+    let keeper = Address::generate(&env);
+
+    token.transfer(&retainor, &retainee, &500);
+    env.ledger().set_timestamp(1_000_000);
+    contract.add_retainer_balance(&retainor, &retainee, &1_000, &token.address);
+    contract.set_expiry(&retainor, &retainee, &2_000_000);
+    contract.require_bond(&retainor, &retainee, &300, &Address::generate(&env));
+    contract.post_bond(&retainor, &retainee, &300);
+
+    env.ledger().set_timestamp(2_000_001);
+    assert_eq!(contract.sweep_expired(&retainor, &retainee, &keeper), 1_000);
+    assert_eq!(token.balance(&retainor), 9_500);
+    assert_eq!(token.balance(&retainee), 500);
+    assert_eq!(token.balance(&contract.address), 0);
+    assert_eq!(contract.bond(&retainor, &retainee), None);
+}